@@ -0,0 +1,116 @@
+use dyrah_shared::map::TiledMap;
+use egor::{app::Context, input::Input, math::Vec2};
+
+use crate::map::View;
+
+// whole-number zoom levels keep every texel the same number of pixels
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 4.0;
+
+// follows a target smoothly, zooms with the mouse wheel and keeps the
+// screen inside the map
+pub struct CameraController {
+    // where the camera is centered, None until it has something to follow
+    center: Option<Vec2>,
+    zoom: f32,
+    // how quickly the camera catches up, 0 to stay locked on the target
+    smoothing: f32,
+}
+
+impl CameraController {
+    pub fn new(smoothing: f32) -> Self {
+        Self {
+            center: None,
+            zoom: MIN_ZOOM,
+            smoothing,
+        }
+    }
+
+    // jumps straight to the next target instead of gliding over, e.g. after
+    // changing maps
+    pub fn reset(&mut self) {
+        self.center = None;
+    }
+
+    pub fn update(
+        &mut self,
+        input: &Input,
+        tiled: &TiledMap,
+        target: Vec2,
+        screen_size: Vec2,
+        dt: f32,
+    ) {
+        let scroll = input.mouse_scroll();
+        if scroll != 0.0 {
+            self.zoom = (self.zoom + scroll.signum()).clamp(MIN_ZOOM, MAX_ZOOM);
+        }
+
+        let center = match self.center {
+            Some(center) if self.smoothing > 0.0 => {
+                // frame rate independent easing toward the target
+                center + (target - center) * (1.0 - (-self.smoothing * dt).exp())
+            }
+            _ => target,
+        };
+        self.center = Some(self.clamp(tiled, center, screen_size));
+    }
+
+    // infinite maps have no edges, and maps smaller than the screen are
+    // kept in the middle of it
+    fn clamp(&self, tiled: &TiledMap, center: Vec2, screen_size: Vec2) -> Vec2 {
+        if tiled.infinite {
+            return center;
+        }
+
+        let half_view = screen_size / 2.0 / self.zoom;
+        let map_size = Vec2::new(
+            (tiled.width * tiled.tilewidth) as f32,
+            (tiled.height * tiled.tileheight) as f32,
+        );
+        let min = half_view;
+        let max = map_size - half_view;
+
+        Vec2::new(
+            match min.x <= max.x {
+                true => center.x.clamp(min.x, max.x),
+                false => map_size.x / 2.0,
+            },
+            match min.y <= max.y {
+                true => center.y.clamp(min.y, max.y),
+                false => map_size.y / 2.0,
+            },
+        )
+    }
+
+    // snapped to whole screen pixels so pixel art doesn't shimmer while moving
+    fn snapped_center(&self) -> Option<Vec2> {
+        self.center
+            .map(|center| (center * self.zoom).round() / self.zoom)
+    }
+
+    pub fn apply(&self, ctx: &mut Context) {
+        let Some(center) = self.snapped_center() else {
+            return;
+        };
+        let camera = ctx.graphics.camera();
+        camera.target(center);
+        camera.set_zoom(self.zoom);
+    }
+
+    // the part of the world on screen
+    pub fn view(&self, screen_size: Vec2) -> Option<View> {
+        let center = self.snapped_center()?;
+        let half_view = screen_size / 2.0 / self.zoom;
+        Some(View {
+            min: center - half_view,
+            max: center + half_view,
+        })
+    }
+
+    pub fn screen_to_world(&self, screen_pos: Vec2, screen_size: Vec2) -> Option<Vec2> {
+        Some(
+            self.view(screen_size)?
+                .screen_to_world(screen_pos, screen_size),
+        )
+    }
+}
@@ -1,3 +1,4 @@
+use dyrah_shared::conditions::ConditionKind;
 use egor::math::Vec2;
 use serde::{Deserialize, Serialize};
 
@@ -13,9 +14,45 @@ pub struct TargetWorldPos {
     pub vec: Vec2,
 }
 
+#[derive(Debug, Default)]
+pub struct Floor {
+    pub z: i32,
+}
+
 #[derive(Debug)]
 pub struct Sprite {
     pub anim: Animation,
     pub frame_size: Vec2,
     pub sprite_size: Vec2,
 }
+
+impl Sprite {
+    // the sprite's own box (min, max) at `pos`, not the padding around it in
+    // the frame
+    pub fn bounds(&self, pos: Vec2) -> (Vec2, Vec2) {
+        let frame_at = pos + self.anim.offset(self.frame_size, self.sprite_size);
+        let min = frame_at + (self.frame_size - self.sprite_size) * Vec2::new(0.5, 1.0);
+        (min, min + self.sprite_size)
+    }
+}
+
+#[derive(Debug)]
+pub struct Name {
+    pub name: String,
+}
+
+// only percentages are sent by the server
+#[derive(Debug)]
+pub struct Health {
+    pub percent: u8,
+}
+
+#[derive(Debug)]
+pub struct Mana {
+    pub percent: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct Conditions {
+    pub kinds: Vec<ConditionKind>,
+}
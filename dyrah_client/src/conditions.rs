@@ -0,0 +1,66 @@
+use dyrah_shared::conditions::ConditionKind;
+use egor::{app::Context, math::Vec2, render::Color};
+
+use crate::ui::Screen;
+
+// the local player's icons, in screen pixels under the target frame
+const ICONS_AT: Vec2 = Vec2::new(10.0, 60.0);
+const ICON_SIZE: f32 = 18.0;
+const ICON_GAP: f32 = 4.0;
+const ICON_TEXT_SIZE: f32 = 12.0;
+
+// how see-through the invisible look to those who can still see them
+const INVISIBLE_ALPHA: f32 = 0.4;
+
+fn color(kind: ConditionKind) -> [f32; 3] {
+    match kind {
+        ConditionKind::Poison => [0.4, 0.9, 0.3],
+        ConditionKind::Burning => [1.0, 0.5, 0.2],
+        ConditionKind::Electrified => [0.7, 0.5, 1.0],
+        ConditionKind::Paralyze => [0.6, 0.6, 0.8],
+        ConditionKind::Haste => [1.0, 0.9, 0.4],
+        ConditionKind::Invisibility => [0.8, 0.8, 0.8],
+        ConditionKind::Regeneration => [0.4, 0.7, 1.0],
+    }
+}
+
+fn icon_label(kind: ConditionKind) -> &'static str {
+    match kind {
+        ConditionKind::Poison => "P",
+        ConditionKind::Burning => "B",
+        ConditionKind::Electrified => "E",
+        ConditionKind::Paralyze => "Z",
+        ConditionKind::Haste => "H",
+        ConditionKind::Invisibility => "I",
+        ConditionKind::Regeneration => "R",
+    }
+}
+
+// what a sprite is drawn multiplied by; the harmful conditions show on it
+pub fn tint(conditions: &[ConditionKind]) -> Color {
+    let [r, g, b] = conditions
+        .iter()
+        .find(|c| c.is_harmful())
+        .map_or([1.0; 3], |&c| color(c));
+    let alpha = match conditions.contains(&ConditionKind::Invisibility) {
+        true => INVISIBLE_ALPHA,
+        false => 1.0,
+    };
+    Color::new([r, g, b, alpha])
+}
+
+pub fn draw_icons(ctx: &mut Context, screen: &Screen, conditions: &[ConditionKind]) {
+    for (i, &kind) in conditions.iter().enumerate() {
+        let at = ICONS_AT + Vec2::new(i as f32 * (ICON_SIZE + ICON_GAP), 0.0);
+        let [r, g, b] = color(kind);
+
+        screen.rect(ctx, at, Vec2::splat(ICON_SIZE), Color::new([r, g, b, 0.9]));
+        screen.text(
+            ctx,
+            at + (ICON_SIZE - ICON_TEXT_SIZE) / 2.0,
+            icon_label(kind),
+            ICON_TEXT_SIZE,
+            Color::BLACK,
+        );
+    }
+}
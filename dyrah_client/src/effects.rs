@@ -0,0 +1,129 @@
+use dyrah_shared::messages::EffectKind;
+use egor::{app::Context, math::Vec2};
+use glam::IVec2;
+
+use crate::{map::Map, sprite::Animation};
+
+// the effects sheet has a row of frames for each kind of effect
+const SHEET_ROWS: usize = 9;
+const SHEET_COLS: usize = 8;
+
+// projectiles spin through their row for as long as they fly
+const PROJECTILE_FRAME_TIME: f32 = 0.05;
+// of a tile
+const PROJECTILE_SCALE: f32 = 0.5;
+
+struct TileEffect {
+    anim: Animation,
+    tiles: Vec<IVec2>,
+    floor: i32,
+    // seconds until it starts
+    delay: f32,
+}
+
+struct Projectile {
+    anim: Animation,
+    origin: IVec2,
+    target: IVec2,
+    floor: i32,
+    duration: f32,
+    age: f32,
+}
+
+// short-lived animations the server asks for, played once where they land or
+// along the way for projectiles
+#[derive(Default)]
+pub struct Effects {
+    tile_effects: Vec<TileEffect>,
+    projectiles: Vec<Projectile>,
+}
+
+impl Effects {
+    pub fn play(
+        &mut self,
+        effect: EffectKind,
+        tiles: Vec<IVec2>,
+        floor: i32,
+        duration: f32,
+        delay: f32,
+    ) {
+        let frame_time = duration / SHEET_COLS as f32;
+        self.tile_effects.push(TileEffect {
+            anim: Animation::row(SHEET_ROWS, SHEET_COLS, effect.row(), frame_time).once(),
+            tiles,
+            floor,
+            delay,
+        });
+    }
+
+    pub fn throw(
+        &mut self,
+        effect: EffectKind,
+        origin: IVec2,
+        target: IVec2,
+        floor: i32,
+        duration: f32,
+    ) {
+        self.projectiles.push(Projectile {
+            anim: Animation::row(SHEET_ROWS, SHEET_COLS, effect.row(), PROJECTILE_FRAME_TIME),
+            origin,
+            target,
+            floor,
+            duration,
+            age: 0.0,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.tile_effects.clear();
+        self.projectiles.clear();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.tile_effects.retain_mut(|e| {
+            if e.delay > 0.0 {
+                e.delay -= dt;
+            } else {
+                e.anim.update(dt);
+            }
+            !e.anim.is_finished()
+        });
+        self.projectiles.retain_mut(|p| {
+            p.age += dt;
+            p.anim.update(dt);
+            p.age < p.duration
+        });
+    }
+
+    pub fn draw(&self, ctx: &mut Context, map: &Map, floor: i32, texture: usize) {
+        let tile_size = Vec2::new(map.tiled.tilewidth as f32, map.tiled.tileheight as f32);
+        // tiles are drawn a row below their position
+        let tile_corner = |tile_pos: IVec2| map.tiled.tile_to_world(tile_pos + IVec2::Y);
+
+        for effect in &self.tile_effects {
+            if effect.floor != floor || effect.delay > 0.0 {
+                continue;
+            }
+            for &tile_pos in &effect.tiles {
+                ctx.graphics
+                    .rect()
+                    .at(tile_corner(tile_pos))
+                    .size(tile_size)
+                    .texture(texture)
+                    .uv(effect.anim.frame());
+            }
+        }
+
+        let size = tile_size * PROJECTILE_SCALE;
+        for projectile in self.projectiles.iter().filter(|p| p.floor == floor) {
+            let t = projectile.age / projectile.duration;
+            let at = tile_corner(projectile.origin).lerp(tile_corner(projectile.target), t);
+            ctx.graphics
+                .rect()
+                .at(at + (tile_size - size) / 2.0)
+                .size(size)
+                .texture(texture)
+                .uv(projectile.anim.frame());
+        }
+    }
+}
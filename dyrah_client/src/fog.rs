@@ -0,0 +1,174 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use bincode::{deserialize, serialize};
+use dyrah_shared::{
+    los::visible_tiles,
+    map::{TiledMap, is_map_name},
+};
+use egor::{app::Context, math::Vec2, render::Color};
+use glam::{IVec2, IVec3};
+
+use crate::map::View;
+
+// tiles the player has never seen are black, tiles seen before but not
+// right now are dimmed
+const UNEXPLORED: f32 = 1.0;
+const EXPLORED: f32 = 0.6;
+
+// each character's explored tiles, per map
+const EXPLORED_DIR: &str = "cache/explored";
+
+// every tile the player has seen on a map, kept on disk so the map stays
+// uncovered between sessions
+#[derive(Default)]
+pub struct Explored {
+    path: Option<PathBuf>,
+    tiles: HashSet<IVec3>,
+    unsaved: bool,
+}
+
+impl Explored {
+    // kept by character name, the same way the server saves characters; a
+    // map whose name can't be a file name is never saved
+    pub fn load(character: &str, map: &str) -> Self {
+        if !is_map_name(map) {
+            return Self::default();
+        }
+        let path = PathBuf::from(format!(
+            "{}/{}/{}.bin",
+            EXPLORED_DIR,
+            character.to_lowercase(),
+            map
+        ));
+        let tiles = fs::read(&path)
+            .ok()
+            .and_then(|bytes| deserialize::<Vec<[i32; 3]>>(&bytes).ok())
+            .map(|tiles| tiles.into_iter().map(IVec3::from_array).collect())
+            .unwrap_or_default();
+
+        Self {
+            path: Some(path),
+            tiles,
+            unsaved: false,
+        }
+    }
+
+    pub fn contains(&self, tile_pos: IVec3) -> bool {
+        self.tiles.contains(&tile_pos)
+    }
+
+    // tiles are only ever added, so a change in count means there's more
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn tiles_on(&self, floor: i32) -> impl Iterator<Item = IVec2> + '_ {
+        self.tiles
+            .iter()
+            .filter(move |t| t.z == floor)
+            .map(|t| t.truncate())
+    }
+
+    fn extend(&mut self, tiles: impl IntoIterator<Item = IVec3>) {
+        for tile_pos in tiles {
+            self.unsaved |= self.tiles.insert(tile_pos);
+        }
+    }
+
+    pub fn save(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.unsaved {
+            return;
+        }
+
+        let tiles: Vec<[i32; 3]> = self.tiles.iter().map(|t| t.to_array()).collect();
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, serialize(&tiles).unwrap()));
+        match result {
+            Ok(()) => self.unsaved = false,
+            Err(e) => eprintln!("Failed to save explored tiles to {}: {}", path.display(), e),
+        }
+    }
+}
+
+// the same line of sight the server uses to decide who we're told about
+#[derive(Default)]
+pub struct Fog {
+    origin: Option<IVec3>,
+    visible: HashSet<IVec2>,
+    explored: Explored,
+}
+
+impl Fog {
+    pub fn new(explored: Explored) -> Self {
+        Self {
+            origin: None,
+            visible: HashSet::new(),
+            explored,
+        }
+    }
+
+    pub fn explored(&self) -> &Explored {
+        &self.explored
+    }
+
+    pub fn explored_mut(&mut self) -> &mut Explored {
+        &mut self.explored
+    }
+
+    // sight only changes when the player moves or the map around them does
+    pub fn update(&mut self, tiled: &TiledMap, player_tile: Option<IVec3>) {
+        if player_tile == self.origin {
+            return;
+        }
+        self.origin = player_tile;
+
+        let Some(origin) = player_tile else {
+            self.visible.clear();
+            return;
+        };
+        self.visible = visible_tiles(origin.truncate(), |pos| {
+            tiled.blocking("colliders", pos.extend(origin.z)).sight
+        });
+        self.explored
+            .extend(self.visible.iter().map(|pos| pos.extend(origin.z)));
+    }
+
+    // streamed chunks can open up or block sight without the player moving
+    pub fn invalidate(&mut self) {
+        self.origin = None;
+    }
+
+    pub fn draw(&self, ctx: &mut Context, tiled: &TiledMap, view: &View) {
+        let Some(origin) = self.origin else {
+            return;
+        };
+        let tile_size = Vec2::new(tiled.tilewidth as f32, tiled.tileheight as f32);
+        let min = tiled.world_to_tile(view.min) - IVec2::ONE;
+        let max = tiled.world_to_tile(view.max) + IVec2::ONE;
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = IVec2::new(x, y);
+                if self.visible.contains(&pos) {
+                    continue;
+                }
+
+                let alpha = match self.explored.contains(pos.extend(origin.z)) {
+                    true => EXPLORED,
+                    false => UNEXPLORED,
+                };
+                // tiles are drawn a row below their position, like the map's
+                ctx.graphics
+                    .rect()
+                    .at(tiled.tile_to_world(pos + IVec2::Y))
+                    .size(tile_size)
+                    .color(Color::new([0.0, 0.0, 0.0, alpha]));
+            }
+        }
+    }
+}
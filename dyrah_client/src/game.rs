@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, env, path::Path};
 
 use bincode::{deserialize, serialize};
 use egor::{
@@ -7,6 +7,7 @@ use egor::{
     math::Vec2,
     render::Color,
 };
+use glam::{IVec2, IVec3};
 use secs::{Entity, World};
 use wrym::{
     client::{Client, ClientEvent},
@@ -16,43 +17,136 @@ use wrym::{
 use dyrah_shared::{
     NetId,
     components::Player,
+    conditions::{STEP_INTERVAL, step_interval},
+    map::{BundleFile, DEFAULT_MAP, MapBundle, MapError, TiledMap, is_map_name, list_maps},
     messages::{ClientInput, ClientMessage, ServerMessage},
 };
 
 use crate::{
-    components::{Sprite, TargetWorldPos, WorldPos},
-    map::Map,
+    camera::CameraController,
+    components::{Conditions, Floor, Health, Mana, Name, Sprite, TargetWorldPos, WorldPos},
+    conditions,
+    effects::Effects,
+    fog::{Explored, Fog},
+    hotkeys::Hotkeys,
+    light::{Lighting, PLAYER_LIGHT, PointLight},
+    map::{Map, RoofFade, TileAnimator, View},
+    minimap::{Marker, Minimap},
+    nameplates::{CombatText, NamePlate, Relations},
+    picking::Pick,
     sprite::Animation,
+    targeting::{TargetInfo, Targeting},
+    ui::Screen,
 };
 
+// maps downloaded from the server, kept per map and version
+const CACHE_DIR: &str = "cache/maps";
+
+// how often explored tiles are written out, in seconds
+const EXPLORED_SAVE_INTERVAL: f32 = 10.0;
+
+// how quickly the camera catches up with the player, 0 to stay locked on
+const CAMERA_SMOOTHING: f32 = 8.0;
+
+const PORTAL_MARKER: [f32; 4] = [0.7, 0.3, 1.0, 1.0];
+const HOVER_HIGHLIGHT: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
+const TARGET_HIGHLIGHT: [f32; 4] = [1.0, 0.0, 0.0, 0.3];
+
+// played unless another character is named on the command line
+const DEFAULT_CHARACTER: &str = "Player";
+
+fn cache_dir(map: &str, hash: u64) -> String {
+    format!("{}/{}-{:016x}", CACHE_DIR, map, hash)
+}
+
+// a map being downloaded from the server, file by file
+struct Download {
+    map: String,
+    hash: u64,
+    files: Vec<BundleFile>,
+    remaining: usize,
+}
+
 pub struct Game {
     client: Client<Transport>,
+    character: String,
     world: World,
-    map: Map,
+    maps: HashMap<String, Map>,
+    map_name: String,
+    camera: CameraController,
+    hover: Option<Pick>,
+    targeting: Targeting,
+    tile_animator: TileAnimator,
+    roofs: RoofFade,
+    fog: Fog,
+    explored_save_timer: f32,
+    minimap: Minimap,
+    lighting: Lighting,
+    relations: Relations,
+    combat_text: CombatText,
+    effects: Effects,
+    hotkeys: Hotkeys,
     lobby: HashMap<NetId, Entity>,
     last_input_time: f32,
     player_tex: Option<usize>,
+    effects_tex: Option<usize>,
     player: Option<Entity>,
     player_id: Option<NetId>,
+    download: Option<Download>,
+    loading: Option<(String, Map)>,
+    queued: Vec<ServerMessage>,
 }
 
 impl Game {
     pub fn new() -> Self {
         Self {
             client: Client::new(Transport::new("127.0.0.1:0"), "127.0.0.1:8080"),
+            character: env::args()
+                .nth(1)
+                .unwrap_or_else(|| DEFAULT_CHARACTER.to_string()),
             world: World::default(),
-            map: Map::new("assets/map.json"),
+            maps: list_maps("assets")
+                .into_iter()
+                .filter_map(|(name, path)| match Map::new(&path) {
+                    Ok(map) => Some((name, map)),
+                    Err(e) => {
+                        eprintln!("Failed to load {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+            map_name: DEFAULT_MAP.to_string(),
+            camera: CameraController::new(CAMERA_SMOOTHING),
+            hover: None,
+            targeting: Targeting::default(),
+            tile_animator: TileAnimator::default(),
+            roofs: RoofFade::default(),
+            fog: Fog::default(),
+            explored_save_timer: 0.0,
+            minimap: Minimap::default(),
+            lighting: Lighting::default(),
+            relations: Relations::default(),
+            combat_text: CombatText::default(),
+            effects: Effects::default(),
+            hotkeys: Hotkeys::default(),
             lobby: HashMap::new(),
             last_input_time: 0.0,
             player_tex: None,
+            effects_tex: None,
             player: None,
             player_id: None,
+            download: None,
+            loading: None,
+            queued: Vec::new(),
         }
     }
 
     pub fn load(&mut self, ctx: &mut InitContext) {
-        self.map.load(ctx);
+        for map in self.maps.values_mut() {
+            map.load(|bytes| ctx.load_texture(bytes));
+        }
         self.player_tex = Some(ctx.load_texture(include_bytes!("../../assets/wizard.png")));
+        self.effects_tex = Some(ctx.load_texture(include_bytes!("../../assets/effects.png")));
     }
 
     pub fn handle_events(&mut self) {
@@ -61,6 +155,11 @@ impl Game {
                 ClientEvent::Connected(id) => {
                     println!("Connected to server!");
                     self.player_id = Some(id);
+
+                    let msg = ClientMessage::Login {
+                        name: self.character.clone(),
+                    };
+                    self.client.send_reliable(&serialize(&msg).unwrap(), true);
                 }
                 ClientEvent::Disconnected => {
                     println!("Lost connection to server");
@@ -73,15 +172,145 @@ impl Game {
         }
     }
 
+    fn map(&self) -> Option<&Map> {
+        self.maps.get(&self.map_name)
+    }
+
+    // uses a previously downloaded copy if there is one, otherwise asks the
+    // server for its copy
+    fn fetch_map(&mut self, map: &str, hash: u64) {
+        let cached = list_maps(&cache_dir(map, hash))
+            .into_iter()
+            .find(|(name, _)| name == map)
+            .and_then(|(_, path)| Map::new(&path).ok())
+            .filter(|cached| cached.hash == hash);
+        if let Some(cached) = cached {
+            self.loading = Some((map.to_string(), cached));
+            return;
+        }
+
+        println!("Downloading map {}..", map);
+        self.download = Some(Download {
+            map: map.to_string(),
+            hash,
+            files: Vec::new(),
+            remaining: 0,
+        });
+
+        let msg = ClientMessage::RequestMap {
+            map: map.to_string(),
+        };
+        self.client.send_reliable(&serialize(&msg).unwrap(), true);
+    }
+
+    fn finish_download(&mut self) {
+        let Some(download) = self.download.take() else {
+            return;
+        };
+        let dir = cache_dir(&download.map, download.hash);
+        let bundle = MapBundle {
+            files: download.files,
+            missing: Vec::new(),
+        };
+
+        let map = bundle
+            .save(Path::new(&dir))
+            .map_err(MapError::from)
+            .and_then(|path| Map::new(&path));
+        match map {
+            Ok(map) if map.hash == download.hash => {
+                println!("Downloaded map {}", download.map);
+                self.loading = Some((download.map, map));
+            }
+            Ok(_) => {
+                eprintln!("Downloaded map {} doesn't match the server's", download.map);
+                self.queued.clear();
+            }
+            Err(e) => {
+                eprintln!("Failed to load downloaded map {}: {}", download.map, e);
+                self.queued.clear();
+            }
+        }
+    }
+
     fn handle_server_messages(&mut self, msg: ServerMessage) {
+        // everything else waits until the map being fetched is ready
+        let fetching = self.download.is_some() || self.loading.is_some();
+        let is_download = matches!(
+            msg,
+            ServerMessage::MapDownload { .. } | ServerMessage::MapFilePart { .. }
+        );
+        if fetching && !is_download {
+            self.queued.push(msg);
+            return;
+        }
+
         match msg {
-            ServerMessage::PlayerSpawned { id, position } => {
-                println!("Player {} spawned!", id);
+            ServerMessage::MapChanged {
+                map,
+                hash,
+                position,
+                floor,
+            } => {
+                // the name ends up in the paths of the map's cache and fog
+                if !is_map_name(&map) {
+                    eprintln!("Server sent a bad map name {:?}", map);
+                    return;
+                }
+                // our copy is missing or differs from the server's
+                if self.maps.get(&map).is_none_or(|m| m.hash != hash) {
+                    self.fetch_map(&map, hash);
+                    self.queued.push(ServerMessage::MapChanged {
+                        map,
+                        hash,
+                        position,
+                        floor,
+                    });
+                    return;
+                }
+
+                println!("Entering {} at {:?} on floor {}", map, position, floor);
+                self.map_name = map;
+                self.roofs = RoofFade::default();
+                self.camera.reset();
+                self.targeting.reset();
+                self.combat_text.clear();
+                self.effects.clear();
+                self.fog.explored_mut().save();
+                self.fog = Fog::new(Explored::load(&self.character, &self.map_name));
+
+                // infinite maps are streamed in by the server as we get close;
+                // a copy of our own may have tiles, but only the streamed ones count
+                let current = self.maps.get_mut(&self.map_name).unwrap();
+                if current.tiled.infinite {
+                    current.clear_chunks();
+                }
+
+                for (_, p) in self.lobby.drain() {
+                    self.world.despawn(p);
+                }
+                self.player = None;
+            }
+            ServerMessage::PlayerSpawned {
+                id,
+                name,
+                position,
+                floor,
+                health,
+                mana,
+                conditions,
+            } => {
+                println!("{} spawned!", name);
 
                 let player = self.world.spawn((
                     Player,
+                    Name { name },
+                    Health { percent: health },
+                    Mana { percent: mana },
+                    Conditions { kinds: conditions },
                     WorldPos { vec: position },
                     TargetWorldPos { vec: position },
+                    Floor { z: floor },
                     Sprite {
                         anim: Animation::new(1, 6, 6, 0.2),
                         frame_size: Vec2::splat(64.0),
@@ -90,30 +319,218 @@ impl Game {
                 ));
 
                 self.lobby.insert(id, player);
-                if self.player_id.is_some() {
+                if Some(id) == self.player_id {
                     self.player = Some(player);
                 }
             }
             ServerMessage::PlayerDespawned { id } => {
                 println!("Player {} disappeared", id);
 
-                self.lobby.remove(&id).map(|p| self.world.despawn(p));
+                if let Some(p) = self.lobby.remove(&id) {
+                    self.world.despawn(p);
+                }
+                self.targeting.forget(id);
+            }
+            ServerMessage::HealthChanged {
+                id,
+                health,
+                change,
+                source,
+            } => {
+                let Some(&player) = self.lobby.get(&id) else {
+                    return;
+                };
+                self.world.get_mut::<Health>(player).unwrap().percent = health;
+
+                let (min, max) = {
+                    let pos = self.world.get::<WorldPos>(player).unwrap();
+                    self.world.get::<Sprite>(player).unwrap().bounds(pos.vec)
+                };
+                self.combat_text.push((min + max) / 2.0, change);
+
+                match source {
+                    Some(source) if Some(id) == self.player_id => self.relations.fought(source),
+                    Some(source) if Some(source) == self.player_id => self.relations.fought(id),
+                    _ => {}
+                }
+            }
+            ServerMessage::ManaChanged { id, mana } => {
+                if let Some(&player) = self.lobby.get(&id) {
+                    self.world.get_mut::<Mana>(player).unwrap().percent = mana;
+                }
             }
-            ServerMessage::PlayerMoved { id, position } => {
+            ServerMessage::ConditionsChanged { id, conditions } => {
+                if let Some(&player) = self.lobby.get(&id) {
+                    self.world.get_mut::<Conditions>(player).unwrap().kinds = conditions;
+                }
+            }
+            ServerMessage::PartyChanged { members } => self.relations.set_party(members),
+            ServerMessage::TileEffect {
+                effect,
+                tiles,
+                floor,
+                duration,
+                delay,
+            } => self.effects.play(effect, tiles, floor, duration, delay),
+            ServerMessage::Projectile {
+                effect,
+                origin,
+                target,
+                floor,
+                duration,
+            } => self.effects.throw(effect, origin, target, floor, duration),
+            ServerMessage::Runes { runes } => self.hotkeys.set_runes(runes),
+            ServerMessage::TextMessage { text } => println!("{}", text),
+            ServerMessage::PlayerMoved {
+                id,
+                position,
+                floor,
+            } => {
                 println!("Player {} moving..", id);
 
                 if let Some(&player) = self.lobby.get(&id) {
                     let mut target_pos = self.world.get_mut::<TargetWorldPos>(player).unwrap();
                     target_pos.vec = position;
+                    self.world.get_mut::<Floor>(player).unwrap().z = floor;
                 }
             }
+            ServerMessage::MapDownload { map, hash, files } => {
+                let Some(download) = self.download.as_mut().filter(|d| d.map == map) else {
+                    return;
+                };
+                download.hash = hash;
+                download.remaining = files.iter().map(|&(_, len)| len as usize).sum();
+                download.files = files
+                    .into_iter()
+                    .map(|(path, len)| BundleFile {
+                        path,
+                        bytes: vec![0; len as usize],
+                    })
+                    .collect();
+
+                // empty files have no parts to wait for, so a map of nothing
+                // but those is already here
+                if download.remaining == 0 {
+                    self.finish_download();
+                }
+            }
+            ServerMessage::MapFilePart {
+                map,
+                path,
+                offset,
+                bytes,
+            } => {
+                let Some(download) = self.download.as_mut().filter(|d| d.map == map) else {
+                    return;
+                };
+                let Some(file) = download.files.iter_mut().find(|f| f.path == path) else {
+                    return;
+                };
+                let (start, end) = (offset as usize, offset as usize + bytes.len());
+                if end > file.bytes.len() {
+                    return;
+                }
+
+                file.bytes[start..end].copy_from_slice(&bytes);
+                download.remaining = download.remaining.saturating_sub(bytes.len());
+                if download.remaining == 0 {
+                    self.finish_download();
+                }
+            }
+            ServerMessage::MapChunk { map, chunk, layers } => {
+                let Some(map) = self.maps.get_mut(&map) else {
+                    return;
+                };
+                for (layer, data) in layers {
+                    map.write_chunk(layer, chunk, data);
+                }
+                self.fog.invalidate();
+            }
+            ServerMessage::MapChunkUnloaded { map, chunk } => {
+                if let Some(map) = self.maps.get_mut(&map) {
+                    map.remove_chunk(chunk);
+                }
+            }
+            ServerMessage::WorldTime { time } => self.lighting.set_time(time),
+            ServerMessage::ShutdownWarning { seconds } => {
+                println!("Server shutting down in {} seconds!", seconds);
+            }
+            ServerMessage::Disconnected { reason } => {
+                println!("Disconnected by server: {}", reason);
+                self.fog.explored_mut().save();
+
+                for (_, p) in self.lobby.drain() {
+                    self.world.despawn(p);
+                }
+                self.player = None;
+            }
         }
     }
 
     pub fn update(&mut self, ctx: &mut Context) {
         self.client.poll();
 
-        let mouse_pos = ctx.input.mouse_position();
+        // a fetched map can only get its textures here, then whatever arrived
+        // while fetching it is handled in order
+        if let Some((name, mut map)) = self.loading.take() {
+            map.load(|bytes| ctx.graphics.load_texture(bytes));
+            self.maps.insert(name, map);
+
+            for msg in std::mem::take(&mut self.queued) {
+                self.handle_server_messages(msg);
+            }
+        }
+
+        self.tile_animator.update(ctx.timer.delta);
+        self.lighting.update(ctx.timer.delta);
+        self.relations.update(ctx.timer.delta);
+        self.combat_text.update(ctx.timer.delta);
+        self.effects.update(ctx.timer.delta);
+        self.minimap.update(&ctx.input);
+
+        self.explored_save_timer += ctx.timer.delta;
+        if self.explored_save_timer >= EXPLORED_SAVE_INTERVAL {
+            self.explored_save_timer = 0.0;
+            self.fog.explored_mut().save();
+        }
+        if let Some(map) = self.maps.get(&self.map_name) {
+            let player_tile = self.player_tile(&map.tiled);
+            self.roofs.update(&map.tiled, player_tile, ctx.timer.delta);
+            self.fog.update(&map.tiled, player_tile);
+            if let Some(tile_pos) = player_tile {
+                self.minimap
+                    .refresh(ctx, &self.map_name, map, self.fog.explored(), tile_pos.z);
+            }
+        }
+
+        // what's under the cursor, for clicking to move, targeting and looting
+        let screen_size = ctx.graphics.screen_size();
+        let mouse_pos = self
+            .camera
+            .screen_to_world(ctx.input.mouse_position().into(), screen_size);
+        let hover = match (self.map(), mouse_pos) {
+            (Some(map), Some(world_pos)) => self
+                .player_tile(&map.tiled)
+                .map(|tile_pos| Pick::new(&self.world, &map.tiled, world_pos, tile_pos.z)),
+            _ => None,
+        };
+        self.hover = hover;
+
+        let hovered = self.hover.as_ref().and_then(|pick| {
+            pick.entities
+                .iter()
+                .find_map(|&e| self.net_id(e).filter(|&id| Some(id) != self.player_id))
+        });
+        let nearby = self.nearby_players();
+        let mut messages = Vec::new();
+        let clicked = self
+            .targeting
+            .update(&ctx.input, hovered, &nearby, &mut messages);
+        self.hotkeys.update(&ctx.input, &mut messages);
+        for msg in messages {
+            self.client.send_reliable(&serialize(&msg).unwrap(), true);
+        }
+
         let left = ctx.input.keys_held(&[KeyCode::KeyA, KeyCode::ArrowLeft]);
         let up = ctx.input.keys_held(&[KeyCode::KeyW, KeyCode::ArrowUp]);
         let right = ctx.input.keys_held(&[KeyCode::KeyD, KeyCode::ArrowRight]);
@@ -121,15 +538,22 @@ impl Game {
         let mouse_tile_pos = ctx
             .input
             .mouse_released(MouseButton::Left)
-            .then_some(mouse_pos)
-            .map(|mp| self.map.tiled.world_to_tile(mp.into()));
+            .then(|| Some(self.hover.as_ref().filter(|_| !clicked)?.tile.truncate()))
+            .flatten();
         let moving = left || up || right || down || mouse_tile_pos.is_some();
 
         self.world.query(
-            |_, _: &Player, pos: &mut WorldPos, target_pos: &TargetWorldPos, spr: &mut Sprite| {
+            |_,
+             _: &Player,
+             pos: &mut WorldPos,
+             target_pos: &TargetWorldPos,
+             spr: &mut Sprite,
+             conditions: &Conditions| {
                 if pos.vec != target_pos.vec {
+                    // hasted and paralyzed players walk faster and slower
+                    let speed = 100.0 * STEP_INTERVAL / step_interval(&conditions.kinds);
                     let dir = (target_pos.vec - pos.vec).normalize_or_zero();
-                    pos.vec += dir * 100.0 * ctx.timer.delta;
+                    pos.vec += dir * speed * ctx.timer.delta;
 
                     if dir.x.abs() > dir.y.abs() {
                         spr.anim.flip_x(dir.x < 0.0);
@@ -145,8 +569,14 @@ impl Game {
             },
         );
 
+        if let (Some(map), Some(player)) = (self.maps.get(&self.map_name), self.player) {
+            let target = self.world.get::<WorldPos>(player).unwrap().vec;
+            self.camera
+                .update(&ctx.input, &map.tiled, target, screen_size, ctx.timer.delta);
+        }
+
         self.last_input_time += ctx.timer.delta;
-        if self.last_input_time >= 0.2 && moving {
+        if self.last_input_time >= self.step_interval() && moving {
             self.last_input_time = 0.0;
 
             let msg = ClientMessage::PlayerUpdate {
@@ -162,24 +592,280 @@ impl Game {
         }
     }
 
-    pub fn render(&self, ctx: &mut Context) {
-        ctx.graphics.clear(Color::BLUE);
+    // how often our steps are sent, which the server holds us to
+    fn step_interval(&self) -> f32 {
+        self.player
+            .and_then(|p| self.world.get::<Conditions>(p))
+            .map_or(STEP_INTERVAL, |c| step_interval(&c.kinds))
+    }
+
+    fn net_id(&self, entity: Entity) -> Option<NetId> {
+        self.lobby
+            .iter()
+            .find_map(|(&id, &e)| (e == entity).then_some(id))
+    }
+
+    fn entity_tile(&self, map: &Map, entity: Entity) -> Option<IVec2> {
+        let tile_size = Vec2::new(map.tiled.tilewidth as f32, map.tiled.tileheight as f32);
+        let pos = self.world.get::<WorldPos>(entity)?;
+        Some((pos.vec / tile_size).round().as_ivec2())
+    }
+
+    // other players on our floor, nearest first, for cycling targets
+    fn nearby_players(&self) -> Vec<NetId> {
+        let Some(player) = self.player else {
+            return Vec::new();
+        };
+        let own_pos = self.world.get::<WorldPos>(player).unwrap().vec;
+        let own_floor = self.world.get::<Floor>(player).unwrap().z;
+
+        let mut nearby: Vec<(f32, NetId)> = self
+            .lobby
+            .iter()
+            .filter(|&(_, &e)| e != player)
+            .filter(|&(_, &e)| self.world.get::<Floor>(e).unwrap().z == own_floor)
+            .map(|(&id, &e)| {
+                let pos = self.world.get::<WorldPos>(e).unwrap().vec;
+                (pos.distance_squared(own_pos), id)
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearby.into_iter().map(|(_, id)| id).collect()
+    }
+
+    // the tile the local player is closest to, with their floor
+    fn player_tile(&self, tiled: &TiledMap) -> Option<IVec3> {
+        let player = self.player?;
+        let world_pos = self.world.get::<WorldPos>(player)?;
+        let floor = self.world.get::<Floor>(player)?;
+        let tile_size = Vec2::new(tiled.tilewidth as f32, tiled.tileheight as f32);
+        Some(
+            (world_pos.vec / tile_size)
+                .round()
+                .as_ivec2()
+                .extend(floor.z),
+        )
+    }
+
+    // the map's lights around `view` and the lanterns players carry
+    fn lights(&self, map: &Map, floor: i32, view: &View) -> Vec<PointLight> {
+        let mut lights = map.lights(floor, view);
+        let tile_size = Vec2::new(map.tiled.tilewidth as f32, map.tiled.tileheight as f32);
+
+        self.world
+            .query(|_, _: &Player, pos: &WorldPos, player_floor: &Floor| {
+                if player_floor.z == floor {
+                    lights.push(PointLight {
+                        tile: (pos.vec / tile_size).round().as_ivec2(),
+                        radius: PLAYER_LIGHT,
+                    });
+                }
+            });
+        lights
+    }
+
+    // players on `floor`, ourselves in white, and the portals we've found
+    fn markers(&self, map: &Map, floor: i32) -> Vec<Marker> {
+        let tile_size = Vec2::new(map.tiled.tilewidth as f32, map.tiled.tileheight as f32);
+        let mut markers: Vec<Marker> = map
+            .tiled
+            .get_layers("portals")
+            .filter(|l| l.floor == floor)
+            .flat_map(|l| l.objects.iter().flatten())
+            .map(|o| map.tiled.object_tiles(o).0)
+            .filter(|&tile_pos| self.fog.explored().contains(tile_pos.extend(floor)))
+            .map(|tile| Marker {
+                tile,
+                color: Color::new(PORTAL_MARKER),
+            })
+            .collect();
+
+        // our own marker goes on top
+        let mut own = None;
+        self.world
+            .query(|player, _: &Player, pos: &WorldPos, player_floor: &Floor| {
+                if player_floor.z != floor {
+                    return;
+                }
+
+                let tile = (pos.vec / tile_size).round().as_ivec2();
+                match Some(player) == self.player {
+                    true => own = Some(tile),
+                    false => markers.push(Marker {
+                        tile,
+                        color: Color::GREEN,
+                    }),
+                }
+            });
+        markers.extend(own.map(|tile| Marker {
+            tile,
+            color: Color::WHITE,
+        }));
+        markers
+    }
+
+    // where the player's sprite meets the ground, for depth sorting
+    fn player_base_y(&self, player: Entity) -> f32 {
+        let world_pos = self.world.get::<WorldPos>(player).unwrap();
+        let spr = self.world.get::<Sprite>(player).unwrap();
+        world_pos.vec.y + spr.anim.offset(spr.frame_size, spr.sprite_size).y + spr.frame_size.y
+    }
+
+    fn draw_player(&self, ctx: &mut Context, player: Entity) {
+        let world_pos = self.world.get::<WorldPos>(player).unwrap();
+        let spr = self.world.get::<Sprite>(player).unwrap();
 
-        self.map.draw_tiles(ctx);
+        let tint = self
+            .world
+            .get::<Conditions>(player)
+            .map_or(Color::WHITE, |c| conditions::tint(&c.kinds));
 
+        let draw_pos = world_pos.vec + spr.anim.offset(spr.frame_size, spr.sprite_size);
+        ctx.graphics
+            .rect()
+            .at(draw_pos)
+            .size(spr.frame_size)
+            .texture(self.player_tex.unwrap())
+            .uv(spr.anim.frame())
+            .color(tint);
+    }
+
+    // tall tiles and players are drawn back to front by where they meet the
+    // ground, with players in front of tiles ending on the same line
+    fn draw_sorted(&self, ctx: &mut Context, map: &Map, floor: i32, view: Option<&View>) {
+        let mut players = Vec::new();
         self.world
-            .query(|player, _: &Player, world_pos: &WorldPos, spr: &Sprite| {
-                let draw_pos = world_pos.vec + spr.anim.offset(spr.frame_size, spr.sprite_size);
-                ctx.graphics
-                    .rect()
-                    .at(draw_pos)
-                    .size(Vec2::splat(64.0))
-                    .texture(self.player_tex.unwrap())
-                    .uv(spr.anim.frame());
-
-                if Some(player) == self.player {
-                    ctx.graphics.camera().target(world_pos.vec);
+            .query(|player, _: &Player, player_floor: &Floor| {
+                if player_floor.z == floor {
+                    players.push(player);
                 }
             });
+        let mut players: Vec<(f32, Entity)> = players
+            .into_iter()
+            .map(|p| (self.player_base_y(p), p))
+            .collect();
+        players.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut tiles = map.tall_tiles(floor, view).into_iter().peekable();
+        for (base_y, player) in players {
+            while let Some(tile) = tiles.next_if(|t| t.base_y() <= base_y) {
+                map.draw_quad(ctx, tile, &self.tile_animator);
+            }
+            self.draw_player(ctx, player);
+        }
+        for tile in tiles {
+            map.draw_quad(ctx, tile, &self.tile_animator);
+        }
+    }
+
+    // over the lighting, so they can be read in the dark
+    fn draw_name_plates(&self, ctx: &mut Context, screen: &Screen, floor: i32) {
+        for (&id, &entity) in &self.lobby {
+            if self.world.get::<Floor>(entity).is_none_or(|f| f.z != floor) {
+                continue;
+            }
+            let (Some(name), Some(health), Some(pos), Some(spr)) = (
+                self.world.get::<Name>(entity),
+                self.world.get::<Health>(entity),
+                self.world.get::<WorldPos>(entity),
+                self.world.get::<Sprite>(entity),
+            ) else {
+                continue;
+            };
+
+            let player = self.world.get::<Player>(entity).is_some();
+            let plate = NamePlate {
+                name: &name.name,
+                relation: self.relations.relation(id, self.player_id, player),
+                health: health.percent,
+                mana: self.world.get::<Mana>(entity).map(|m| m.percent),
+            };
+            let (min, max) = spr.bounds(pos.vec);
+            plate.draw(ctx, screen, Vec2::new((min.x + max.x) / 2.0, min.y));
+        }
+    }
+
+    pub fn render(&self, ctx: &mut Context) {
+        // anything past the map's edges is unlit
+        ctx.graphics.clear(Color::BLACK);
+
+        let Some(map) = self.map() else {
+            return;
+        };
+        let mut view_floor = 0;
+        let mut covered = false;
+        let mut view = None;
+
+        if let Some(tile_pos) = self.player_tile(&map.tiled) {
+            view_floor = tile_pos.z;
+            covered = map.tiled.is_covered(tile_pos);
+            self.camera.apply(ctx);
+
+            // only what's around the camera gets drawn
+            view = self.camera.view(ctx.graphics.screen_size());
+        }
+
+        // draw bottom-up, hiding the floors above when the player is underneath them
+        for floor in map.tiled.floors() {
+            if covered && floor > view_floor {
+                break;
+            }
+
+            map.draw_ground(ctx, floor, &self.tile_animator, view.as_ref());
+            self.draw_sorted(ctx, map, floor, view.as_ref());
+            map.draw_overhead(ctx, floor, &self.tile_animator, view.as_ref(), &self.roofs);
+        }
+
+        if let Some(pick) = &self.hover {
+            // an entity under the cursor is highlighted where it stands
+            let tile_pos = pick
+                .entities
+                .first()
+                .and_then(|&e| self.entity_tile(map, e))
+                .unwrap_or(pick.tile.truncate());
+            map.highlight_tile(ctx, tile_pos, Color::new(HOVER_HIGHLIGHT));
+        }
+        if let Some(tile_pos) = self
+            .targeting
+            .target()
+            .and_then(|id| self.lobby.get(&id))
+            .and_then(|&e| self.entity_tile(map, e))
+        {
+            map.highlight_tile(ctx, tile_pos, Color::new(TARGET_HIGHLIGHT));
+        }
+
+        if let Some(view) = &view {
+            let lights = self.lights(map, view_floor, view);
+            self.lighting
+                .draw(ctx, &map.tiled, view_floor, view, &lights);
+            // effects glow, so they show even at night
+            self.effects
+                .draw(ctx, map, view_floor, self.effects_tex.unwrap());
+            self.fog.draw(ctx, &map.tiled, view);
+
+            let screen = Screen::new(*view, ctx.graphics.screen_size());
+            self.draw_name_plates(ctx, &screen, view_floor);
+            self.combat_text.draw(ctx, &screen);
+            self.hotkeys.draw(ctx, &screen);
+            if let Some(own) = self.player.and_then(|p| self.world.get::<Conditions>(p)) {
+                conditions::draw_icons(ctx, &screen, &own.kinds);
+            }
+
+            if let Some(center) = self.player_tile(&map.tiled) {
+                let markers = self.markers(map, center.z);
+                self.minimap.draw(ctx, &screen, center, &markers);
+            }
+
+            let mouse_pos = ctx.input.mouse_position().into();
+            self.targeting.draw(ctx, &screen, mouse_pos, |id| {
+                let entity = *self.lobby.get(&id)?;
+                let name = self.world.get::<Name>(entity)?;
+                let health = self.world.get::<Health>(entity)?;
+                Some(TargetInfo {
+                    name: name.name.clone(),
+                    health: health.percent,
+                })
+            });
+        }
     }
 }
@@ -0,0 +1,77 @@
+use dyrah_shared::messages::ClientMessage;
+use egor::{
+    app::Context,
+    input::{Input, KeyCode},
+    math::Vec2,
+    render::Color,
+};
+
+use crate::ui::Screen;
+
+// what the function keys say; the server casts whatever is an incantation
+const SPELL_KEYS: [(KeyCode, &str, &str); 4] = [
+    (KeyCode::F1, "F1", "exura"),
+    (KeyCode::F2, "F2", "exori flam"),
+    (KeyCode::F3, "F3", "exevo frigo hur"),
+    (KeyCode::F4, "F4", "adori gran flam"),
+];
+
+// the number keys use the player's runes, in order
+const RUNE_KEYS: [KeyCode; 4] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+];
+
+// in screen pixels, from the bottom left
+const MARGIN: f32 = 10.0;
+const LINE_HEIGHT: f32 = 16.0;
+const TEXT_SIZE: f32 = 12.0;
+
+// spells and runes on keys, with the rune charges the server told us about
+#[derive(Default)]
+pub struct Hotkeys {
+    runes: Vec<(String, u32)>,
+}
+
+impl Hotkeys {
+    pub fn set_runes(&mut self, runes: Vec<(String, u32)>) {
+        self.runes = runes;
+    }
+
+    pub fn update(&self, input: &Input, messages: &mut Vec<ClientMessage>) {
+        for (key, _, words) in SPELL_KEYS {
+            if input.key_pressed(key) {
+                messages.push(ClientMessage::Say {
+                    text: words.to_string(),
+                });
+            }
+        }
+
+        for (key, (rune, _)) in RUNE_KEYS.into_iter().zip(&self.runes) {
+            if input.key_pressed(key) {
+                messages.push(ClientMessage::UseRune { rune: rune.clone() });
+            }
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, screen: &Screen) {
+        let spells = SPELL_KEYS
+            .iter()
+            .map(|(_, label, words)| format!("{} {}", label, words));
+        let runes = self
+            .runes
+            .iter()
+            .take(RUNE_KEYS.len())
+            .enumerate()
+            .map(|(i, (rune, charges))| format!("{} {} x{}", i + 1, rune, charges));
+        let lines: Vec<String> = spells.chain(runes).collect();
+
+        let top = screen.size.y - MARGIN - lines.len() as f32 * LINE_HEIGHT;
+        for (i, line) in lines.iter().enumerate() {
+            let at = Vec2::new(MARGIN, top + i as f32 * LINE_HEIGHT);
+            screen.text(ctx, at, line, TEXT_SIZE, Color::WHITE);
+        }
+    }
+}
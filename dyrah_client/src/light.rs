@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use dyrah_shared::{
+    clock::{DAY_LENGTH, ambient_light},
+    los::has_line_of_sight,
+    map::TiledMap,
+};
+use egor::{app::Context, math::Vec2, render::Color};
+use glam::IVec2;
+
+use crate::map::View;
+
+// how far the lantern every player carries reaches, in tiles
+pub const PLAYER_LIGHT: f32 = 3.0;
+
+// the furthest any light reaches, so lights just off screen still count
+pub const MAX_LIGHT_RADIUS: f32 = 8.0;
+
+// darkness falls a deep blue rather than black
+const SHADE: [f32; 3] = [0.0, 0.01, 0.05];
+
+// a light shining from a tile, brightest there and fading out to its radius
+#[derive(Clone, Copy)]
+pub struct PointLight {
+    pub tile: IVec2,
+    pub radius: f32,
+}
+
+#[derive(Default)]
+pub struct Lighting {
+    // seconds into the day, corrected whenever the server sends its clock
+    time: f32,
+}
+
+impl Lighting {
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time = (self.time + dt).rem_euclid(DAY_LENGTH);
+    }
+
+    // maps can fix their light with a float `ambient` property, so caves and
+    // dungeons stay dark at noon
+    pub fn ambient(&self, tiled: &TiledMap) -> f32 {
+        tiled
+            .properties
+            .get_float("ambient")
+            .map_or_else(|| ambient_light(self.time), |a| a as f32)
+    }
+
+    // the light map holds a brightness per tile, and multiplying it over the
+    // scene is darkening each tile by however much light it's missing
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        tiled: &TiledMap,
+        floor: i32,
+        view: &View,
+        lights: &[PointLight],
+    ) {
+        let min = tiled.world_to_tile(view.min) - IVec2::ONE;
+        let max = tiled.world_to_tile(view.max) + IVec2::ONE;
+        let size = max - min + IVec2::ONE;
+        let mut light_map = vec![self.ambient(tiled); (size.x * size.y) as usize];
+
+        // walls cast shadows, and are shared by every light near them
+        let mut blocking = HashMap::new();
+        let mut blocks = |pos: IVec2| {
+            *blocking
+                .entry(pos)
+                .or_insert_with(|| tiled.blocking("colliders", pos.extend(floor)).sight)
+        };
+
+        for light in lights {
+            let reach = light.radius.ceil() as i32;
+            for y in -reach..=reach {
+                for x in -reach..=reach {
+                    let offset = IVec2::new(x, y);
+                    let local = light.tile + offset - min;
+                    if local.cmplt(IVec2::ZERO).any() || local.cmpge(size).any() {
+                        continue;
+                    }
+
+                    let falloff = 1.0 - offset.as_vec2().length() / light.radius;
+                    if falloff <= 0.0
+                        || !has_line_of_sight(light.tile, light.tile + offset, &mut blocks)
+                    {
+                        continue;
+                    }
+
+                    let i = (local.y * size.x + local.x) as usize;
+                    light_map[i] = (light_map[i] + falloff).min(1.0);
+                }
+            }
+        }
+
+        let tile_size = Vec2::new(tiled.tilewidth as f32, tiled.tileheight as f32);
+        for (i, &light) in light_map.iter().enumerate() {
+            if light >= 1.0 {
+                continue;
+            }
+
+            let pos = min + IVec2::new(i as i32 % size.x, i as i32 / size.x);
+            // tiles are drawn a row below their position, like the map's
+            ctx.graphics
+                .rect()
+                .at(tiled.tile_to_world(pos + IVec2::Y))
+                .size(tile_size)
+                .color(Color::new([SHADE[0], SHADE[1], SHADE[2], 1.0 - light]));
+        }
+    }
+}
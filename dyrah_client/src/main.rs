@@ -1,7 +1,18 @@
+mod camera;
 mod components;
+mod conditions;
+mod effects;
+mod fog;
 mod game;
+mod hotkeys;
+mod light;
 mod map;
+mod minimap;
+mod nameplates;
+mod picking;
 mod sprite;
+mod targeting;
+mod ui;
 
 use egor::app::App;
 
@@ -1,112 +1,574 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use dyrah_shared::map::{CHUNK_SIZE, MapBundle, MapError, TiledMap, chunk_positions, decode_gid};
+use egor::{app::Context, math::Vec2, render::Color};
+use glam::{IVec2, IVec3};
+use image::RgbaImage;
 
-use dyrah_shared::map::TiledMap;
-use egor::{
-    app::{Context, InitContext},
-    math::Vec2,
+use crate::{
+    light::{MAX_LIGHT_RADIUS, PointLight},
+    sprite::flip_tile_uv,
 };
 
-pub struct Tileset {
-    dimensions: (u32, u32),
+// one clock drives every animated tile, so all copies of a tile stay in sync
+#[derive(Default)]
+pub struct TileAnimator {
+    elapsed: f64,
+}
+
+impl TileAnimator {
+    pub fn update(&mut self, dt: f32) {
+        self.elapsed += dt as f64;
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        (self.elapsed * 1000.0) as u64
+    }
+}
+
+// roofs over the interior the local player is in fade out, and fade back in
+// once they leave
+pub struct RoofFade {
+    // the interior's tiles (min, max exclusive) and floor
+    area: Option<(IVec2, IVec2, i32)>,
+    alpha: f32,
+}
+
+impl Default for RoofFade {
+    fn default() -> Self {
+        Self {
+            area: None,
+            alpha: 1.0,
+        }
+    }
+}
+
+impl RoofFade {
+    // full fades per second
+    const SPEED: f32 = 4.0;
+
+    pub fn update(&mut self, tiled: &TiledMap, player_tile: Option<IVec3>, dt: f32) {
+        let inside = player_tile.and_then(|tile_pos| {
+            let interior = tiled.interior_at(tile_pos)?;
+            let (min, max) = tiled.object_tiles(interior);
+            Some((min, max, tile_pos.z))
+        });
+
+        let target = match inside {
+            Some(_) => {
+                self.area = inside;
+                0.0
+            }
+            None => 1.0,
+        };
+        let step = Self::SPEED * dt;
+        self.alpha = (self.alpha + (target - self.alpha).clamp(-step, step)).clamp(0.0, 1.0);
+    }
+
+    fn alpha_at(&self, tile_pos: IVec2, floor: i32) -> f32 {
+        match self.area {
+            Some((min, max, z))
+                if z == floor && tile_pos.cmpge(min).all() && tile_pos.cmplt(max).all() =>
+            {
+                self.alpha
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+// the part of the world the camera can see
+#[derive(Clone, Copy)]
+pub struct View {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl View {
+    // where a point on the screen is in the world, for anything laid out in
+    // screen pixels
+    pub fn screen_to_world(&self, screen_pos: Vec2, screen_size: Vec2) -> Vec2 {
+        self.min + screen_pos / screen_size * (self.max - self.min)
+    }
+
+    pub fn world_to_screen(&self, world_pos: Vec2, screen_size: Vec2) -> Vec2 {
+        (world_pos - self.min) / (self.max - self.min) * screen_size
+    }
+}
+
+// a tile's texture and UVs, worked out once when its tileset is loaded
+struct TileSprite {
     texture: usize,
+    uv: [[f32; 2]; 4],
+    size: Vec2,
+    offset: Vec2,
+    animated: bool,
+    // what the tile looks like on the minimap, unless it's see-through
+    color: Option<[f32; 3]>,
+    // taller than a cell or offset out of it, so it's depth sorted
+    tall: bool,
+}
+
+// a tile placed in a layer, positioned once when the layer is built
+pub struct TileQuad {
+    // in layer coordinates
+    tile: IVec2,
+    at: Vec2,
+    gid: u32,
+    base_y: f32,
+}
+
+impl TileQuad {
+    // where the tile meets the ground, which decides what it's drawn over
+    pub fn base_y(&self) -> f32 {
+        self.base_y
+    }
+}
+
+#[derive(Default)]
+struct ChunkGeometry {
+    flat: Vec<TileQuad>,
+    tall: Vec<TileQuad>,
+    lights: Vec<PointLight>,
+}
+
+impl ChunkGeometry {
+    fn push(&mut self, quad: TileQuad, tall: bool) {
+        match tall {
+            true => self.tall.push(quad),
+            false => self.flat.push(quad),
+        }
+    }
+}
+
+// a layer's tiles bucketed by chunk, so drawing only visits chunks on screen
+#[derive(Default)]
+struct LayerGeometry {
+    // drawn over everything else on the floor
+    overhead: bool,
+    // overhead, and hidden over the interior the player is in
+    roof: bool,
+    chunks: HashMap<IVec2, ChunkGeometry>,
 }
 
 pub struct Map {
     pub tiled: TiledMap,
-    sets: HashMap<u32, Tileset>,
+    pub hash: u64,
+    dir: PathBuf,
+    // indexed by GID
+    sprites: Vec<Option<TileSprite>>,
+    // one per layer of `tiled`
+    geometry: Vec<LayerGeometry>,
+    // how far tiles can reach outside their own cell
+    overhang: Vec2,
 }
 
 impl Map {
-    pub fn new(path: &str) -> Self {
-        let tiled = TiledMap::new(path);
-        Self {
+    pub fn new(path: &Path) -> Result<Self, MapError> {
+        let tiled = TiledMap::load(path)?;
+        Ok(Self {
+            hash: MapBundle::collect(path, &tiled)?.hash(),
             tiled,
-            sets: HashMap::new(),
-        }
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            sprites: Vec::new(),
+            geometry: Vec::new(),
+            overhang: Vec2::ZERO,
+        })
     }
 
-    pub fn load(&mut self, ctx: &mut InitContext<'_>) {
+    // textures are loaded at startup and again whenever a map is downloaded,
+    // which happen with different contexts
+    pub fn load(&mut self, mut load_texture: impl FnMut(&[u8]) -> usize) {
+        let tile_size = Vec2::new(self.tiled.tilewidth as f32, self.tiled.tileheight as f32);
+
         for tileset in &self.tiled.tilesets {
-            if let Some(path) = &tileset.image {
-                let bytes = std::fs::read(format!("assets/{}", path)).unwrap();
-                let img = image::load_from_memory(&bytes).unwrap().to_rgba8();
-                let (w, h) = img.dimensions();
-                let tex = ctx.load_texture(&bytes);
-
-                self.sets.insert(
-                    tileset.firstgid,
-                    Tileset {
-                        dimensions: (w, h),
-                        texture: tex,
-                    },
-                );
-
-                println!("Loaded tileset: {} ({}x{})", path, w, h);
+            let (Some(path), Some(tile_w), Some(tile_h)) =
+                (&tileset.image, tileset.tilewidth, tileset.tileheight)
+            else {
+                continue;
+            };
+            let bytes = match std::fs::read(self.dir.join(path)) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("Failed to read tileset {}: {}", path, e);
+                    continue;
+                }
+            };
+            let img = match image::load_from_memory(&bytes) {
+                Ok(img) => img.to_rgba8(),
+                Err(e) => {
+                    eprintln!("Failed to decode tileset {}: {}", path, e);
+                    continue;
+                }
+            };
+            let (img_w, img_h) = img.dimensions();
+            let texture = load_texture(&bytes);
+
+            // laid out the same way the server counts them
+            let Some(grid) = tileset.grid(img_w, img_h) else {
+                continue;
+            };
+            let tilecount = tileset.tile_count().unwrap_or(grid.x * grid.y);
+            let size = Vec2::new(tile_w as f32, tile_h as f32);
+            // add offset when tiles are larger
+            let offset = tileset
+                .tileoffset
+                .as_ref()
+                .map_or(Vec2::ZERO, |o| Vec2::new(o.x as f32, o.y as f32));
+            self.overhang = self
+                .overhang
+                .max((size - tile_size).max(Vec2::ZERO) + offset.abs());
+
+            let last = (tileset.firstgid + tilecount) as usize;
+            if self.sprites.len() < last {
+                self.sprites.resize_with(last, || None);
             }
+            for local_id in 0..tilecount {
+                let Some(origin) = tileset.tile_origin(local_id, grid.x) else {
+                    continue;
+                };
+                let (tile_x, tile_y) = (origin.x, origin.y);
+                let u0 = tile_x as f32 / img_w as f32;
+                let v0 = tile_y as f32 / img_h as f32;
+                let u1 = (tile_x + tile_w) as f32 / img_w as f32;
+                let v1 = (tile_y + tile_h) as f32 / img_h as f32;
+
+                let tile = tileset.get_tile(local_id);
+                // tiles can pick their own minimap color with a `minimap` property
+                let color = tile
+                    .and_then(|t| t.properties.get_color("minimap"))
+                    .map(|c| [c.r, c.g, c.b].map(|c| c as f32 / 255.0))
+                    .or_else(|| average_color(&img, tile_x, tile_y, tile_w, tile_h));
+
+                self.sprites[(tileset.firstgid + local_id) as usize] = Some(TileSprite {
+                    texture,
+                    uv: [[u0, v0], [u1, v0], [u1, v1], [u0, v1]],
+                    size,
+                    offset,
+                    tall: size.y > tile_size.y || offset != Vec2::ZERO,
+                    animated: tile.is_some_and(|t| !t.animation.is_empty()),
+                    color,
+                });
+            }
+
+            println!("Loaded tileset: {} ({}x{})", path, img_w, img_h);
         }
+
+        self.build_geometry();
+    }
+
+    fn sprite(&self, gid: u32) -> Option<&TileSprite> {
+        let (id, _) = decode_gid(gid);
+        self.sprites.get(id as usize)?.as_ref()
+    }
+
+    fn quad(&self, tile_pos: IVec2, gid: u32) -> Option<(TileQuad, bool)> {
+        let sprite = self.sprite(gid)?;
+        let (tile_w, tile_h) = (self.tiled.tilewidth as f32, self.tiled.tileheight as f32);
+
+        // account for Tiled's Y-down & egor's Y-up
+        let at = Vec2::new(
+            tile_pos.x as f32 * tile_w,
+            tile_pos.y as f32 * tile_h - sprite.size.y + tile_h,
+        ) + sprite.offset;
+
+        let base_y = at.y + sprite.size.y;
+        let tile = tile_pos;
+        Some((
+            TileQuad {
+                tile,
+                at,
+                gid,
+                base_y,
+            },
+            sprite.tall,
+        ))
     }
 
-    pub fn draw_tile_layer(&self, ctx: &mut Context, layer_name: &str) {
-        let layer = self.tiled.get_layer(layer_name).unwrap();
-        let (layer_w, layer_h) = (layer.width.unwrap(), layer.height.unwrap());
-        let (tile_w, tile_h) = (self.tiled.tilewidth, self.tiled.tileheight);
+    // tiles with a float `light` property light up that many tiles around them
+    fn light(&self, tile_pos: IVec2, gid: u32) -> Option<PointLight> {
+        let radius = self.tiled.tile_properties(gid)?.get_float("light")? as f32;
+        Some(PointLight {
+            tile: tile_pos - IVec2::Y,
+            radius: radius.min(MAX_LIGHT_RADIUS),
+        })
+    }
 
-        for y in 0..layer_h {
-            for x in 0..layer_w {
-                if let Some(data) = &layer.data {
-                    let tile_id = data[(y * layer_w + x) as usize];
-                    if tile_id == 0 {
-                        continue;
+    fn build_geometry(&mut self) {
+        let geometry = self
+            .tiled
+            .layers
+            .iter()
+            .map(|layer| {
+                let roof = layer.properties.get_bool("roof").unwrap_or(false);
+                let mut geometry = LayerGeometry {
+                    overhead: roof || layer.properties.get_bool("overhead").unwrap_or(false),
+                    roof,
+                    chunks: HashMap::new(),
+                };
+                for (tile_pos, gid) in layer.tiles() {
+                    let chunk = tile_pos.div_euclid(IVec2::splat(CHUNK_SIZE));
+                    if let Some((quad, tall)) = self.quad(tile_pos, gid) {
+                        geometry.chunks.entry(chunk).or_default().push(quad, tall);
+                    }
+                    if let Some(light) = self.light(tile_pos, gid) {
+                        geometry.chunks.entry(chunk).or_default().lights.push(light);
                     }
+                }
+                geometry
+            })
+            .collect();
+
+        self.geometry = geometry;
+    }
+
+    fn build_chunk(&mut self, layer: usize, chunk: IVec2) {
+        let Some(tiled_layer) = self.tiled.layers.get(layer) else {
+            return;
+        };
+        let mut quads = ChunkGeometry::default();
+        // only the chunk's own tiles, which sit a row below where they're read
+        for pos in chunk_positions(chunk) {
+            let gid = tiled_layer.tile_id(pos).unwrap_or(0);
+            if gid == 0 {
+                continue;
+            }
+            let tile_pos = pos + IVec2::Y;
+            if let Some((quad, tall)) = self.quad(tile_pos, gid) {
+                quads.push(quad, tall);
+            }
+            if let Some(light) = self.light(tile_pos, gid) {
+                quads.lights.push(light);
+            }
+        }
+
+        if let Some(geometry) = self.geometry.get_mut(layer) {
+            geometry.chunks.insert(chunk, quads);
+        }
+    }
+
+    // streamed chunks go through here so the geometry stays in step
+    pub fn write_chunk(&mut self, layer: u32, chunk: IVec2, data: Vec<u32>) {
+        self.tiled.write_chunk(layer, chunk, data);
+        self.build_chunk(layer as usize, chunk);
+    }
+
+    pub fn remove_chunk(&mut self, chunk: IVec2) {
+        self.tiled.remove_chunk(chunk);
+        for geometry in &mut self.geometry {
+            geometry.chunks.remove(&chunk);
+        }
+    }
+
+    pub fn clear_chunks(&mut self) {
+        self.tiled.clear_chunks();
+        for geometry in &mut self.geometry {
+            geometry.chunks.clear();
+        }
+    }
+
+    // the chunks that can show up in `view`, allowing for tiles that are
+    // larger than a cell or offset out of it
+    fn visible_chunks(&self, view: &View) -> (IVec2, IVec2) {
+        let tile_size = Vec2::new(self.tiled.tilewidth as f32, self.tiled.tileheight as f32);
+        let to_chunk = |pos: Vec2| {
+            (pos / tile_size)
+                .floor()
+                .as_ivec2()
+                .div_euclid(IVec2::splat(CHUNK_SIZE))
+        };
+
+        (
+            to_chunk(view.min - self.overhang),
+            to_chunk(view.max + self.overhang),
+        )
+    }
+
+    pub fn draw_quad(&self, ctx: &mut Context, quad: &TileQuad, animator: &TileAnimator) {
+        self.draw_faded_quad(ctx, quad, animator, 1.0);
+    }
+
+    fn draw_faded_quad(
+        &self,
+        ctx: &mut Context,
+        quad: &TileQuad,
+        animator: &TileAnimator,
+        alpha: f32,
+    ) {
+        let Some(mut sprite) = self.sprite(quad.gid) else {
+            return;
+        };
+        let mut gid = quad.gid;
+        if sprite.animated {
+            gid = self.tiled.animated_gid(gid, animator.elapsed_ms());
+            let Some(frame) = self.sprite(gid) else {
+                return;
+            };
+            sprite = frame;
+        }
+
+        let (_, flip) = decode_gid(gid);
+        let uv = flip_tile_uv(sprite.uv, flip);
 
-                    if let Some(tileset) = self
-                        .tiled
-                        .tilesets
-                        .iter()
-                        .filter(|set| tile_id >= set.firstgid)
-                        .last()
-                    {
-                        let tex = self.sets[&tileset.firstgid].texture;
-                        let (img_w, img_h) = self.sets[&tileset.firstgid].dimensions;
-                        let tileset_tile_w = tileset.tilewidth.unwrap();
-                        let tileset_tile_h = tileset.tileheight.unwrap();
-                        let tiles_per_row = img_w / tileset_tile_w;
-                        let local_tile_id = tile_id - tileset.firstgid;
-                        let (tile_x, tile_y) = (
-                            local_tile_id % tiles_per_row * tileset_tile_w,
-                            local_tile_id / tiles_per_row * tileset_tile_h,
-                        );
-
-                        let u0 = tile_x as f32 / img_w as f32;
-                        let v0 = tile_y as f32 / img_h as f32;
-                        let u1 = (tile_x + tileset_tile_w) as f32 / img_w as f32;
-                        let v1 = (tile_y + tileset_tile_h) as f32 / img_h as f32;
-
-                        // add offset when tiles are larger
-                        let offset_x = tileset.tileoffset.as_ref().map_or(0, |o| o.x) as f32;
-                        let offset_y = tileset.tileoffset.as_ref().map_or(0, |o| o.y) as f32;
-                        let draw_x = (x * tile_w) as f32 + offset_x;
-                        // account for Tiled's Y-down & egor's Y-up
-                        let draw_y =
-                            (y * tile_h) as f32 - tileset_tile_h as f32 + tile_h as f32 + offset_y;
-
-                        ctx.graphics
-                            .rect()
-                            .at(Vec2::new(draw_x, draw_y))
-                            .size(Vec2::new(tileset_tile_w as f32, tileset_tile_h as f32))
-                            .texture(tex)
-                            .uv([[u0, v0], [u1, v0], [u1, v1], [u0, v1]]);
+        let mut rect = ctx.graphics.rect();
+        rect.at(quad.at)
+            .size(sprite.size)
+            .texture(sprite.texture)
+            .uv(uv);
+        if alpha < 1.0 {
+            rect.color(Color::new([1.0, 1.0, 1.0, alpha]));
+        }
+    }
+
+    // visits the on-screen chunks of every visible layer on `floor`, in layer order
+    fn for_each_chunk<'a>(
+        &'a self,
+        floor: i32,
+        view: Option<&View>,
+        mut f: impl FnMut(&'a LayerGeometry, &'a ChunkGeometry),
+    ) {
+        let visible = view.map(|view| self.visible_chunks(view));
+
+        for (layer, geometry) in self.tiled.layers.iter().zip(&self.geometry) {
+            if !layer.visible || layer.floor != floor {
+                continue;
+            }
+
+            match visible {
+                Some((min, max)) => {
+                    for y in min.y..=max.y {
+                        for x in min.x..=max.x {
+                            if let Some(chunk) = geometry.chunks.get(&IVec2::new(x, y)) {
+                                f(geometry, chunk);
+                            }
+                        }
                     }
                 }
+                None => geometry
+                    .chunks
+                    .values()
+                    .for_each(|chunk| f(geometry, chunk)),
             }
         }
     }
 
-    pub fn draw_tiles(&self, ctx: &mut Context) {
-        for layer in &self.tiled.layers {
-            if layer.visible && layer.data.is_some() {
-                self.draw_tile_layer(ctx, &layer.name);
+    // everything lying flat on the floor, drawn before anything stands on it
+    pub fn draw_ground(
+        &self,
+        ctx: &mut Context,
+        floor: i32,
+        animator: &TileAnimator,
+        view: Option<&View>,
+    ) {
+        self.for_each_chunk(floor, view, |layer, chunk| {
+            if !layer.overhead {
+                for quad in &chunk.flat {
+                    self.draw_quad(ctx, quad, animator);
+                }
+            }
+        });
+    }
+
+    // tall tiles on `floor` from the back to the front, to be drawn among players
+    pub fn tall_tiles(&self, floor: i32, view: Option<&View>) -> Vec<&TileQuad> {
+        let mut tiles = Vec::new();
+        self.for_each_chunk(floor, view, |layer, chunk| {
+            if !layer.overhead {
+                tiles.extend(&chunk.tall);
             }
+        });
+
+        tiles.sort_by(|a, b| a.base_y.total_cmp(&b.base_y));
+        tiles
+    }
+
+    // tints a whole tile, which is drawn a row below its position
+    pub fn highlight_tile(&self, ctx: &mut Context, tile_pos: IVec2, color: Color) {
+        let tile_size = Vec2::new(self.tiled.tilewidth as f32, self.tiled.tileheight as f32);
+        ctx.graphics
+            .rect()
+            .at(self.tiled.tile_to_world(tile_pos + IVec2::Y))
+            .size(tile_size)
+            .color(color);
+    }
+
+    // the color of the topmost tile at a tile position, for the minimap
+    pub fn minimap_color(&self, tile_pos: IVec2, floor: i32) -> Option<[f32; 3]> {
+        // layers hold tiles a row below their position
+        let layer_pos = tile_pos + IVec2::Y;
+
+        self.tiled
+            .layers
+            .iter()
+            .rev()
+            .filter(|l| l.visible && l.floor == floor && l.is_tile_layer())
+            .find_map(|l| {
+                let gid = l.tile_id(layer_pos).filter(|&gid| gid != 0)?;
+                self.sprite(gid)?.color
+            })
+    }
+
+    // the lights on `floor` that can reach into `view`
+    pub fn lights(&self, floor: i32, view: &View) -> Vec<PointLight> {
+        let tile_size = Vec2::new(self.tiled.tilewidth as f32, self.tiled.tileheight as f32);
+        let reach = tile_size * MAX_LIGHT_RADIUS;
+        let view = View {
+            min: view.min - reach,
+            max: view.max + reach,
+        };
+
+        let mut lights = Vec::new();
+        self.for_each_chunk(floor, Some(&view), |_, chunk| {
+            lights.extend(&chunk.lights);
+        });
+        lights
+    }
+
+    pub fn draw_overhead(
+        &self,
+        ctx: &mut Context,
+        floor: i32,
+        animator: &TileAnimator,
+        view: Option<&View>,
+        roofs: &RoofFade,
+    ) {
+        self.for_each_chunk(floor, view, |layer, chunk| {
+            if !layer.overhead {
+                return;
+            }
+
+            for quad in chunk.flat.iter().chain(&chunk.tall) {
+                // roof quads are in layer coordinates, interiors in tile positions
+                let alpha = match layer.roof {
+                    true => roofs.alpha_at(quad.tile - IVec2::Y, floor),
+                    false => 1.0,
+                };
+                if alpha > 0.0 {
+                    self.draw_faded_quad(ctx, quad, animator, alpha);
+                }
+            }
+        });
+    }
+}
+
+// the average of a tile's opaque pixels
+fn average_color(img: &RgbaImage, x: u32, y: u32, w: u32, h: u32) -> Option<[f32; 3]> {
+    let mut sum = [0.0; 3];
+    let mut weight = 0.0;
+
+    for py in y..(y + h).min(img.height()) {
+        for px in x..(x + w).min(img.width()) {
+            let [r, g, b, a] = img.get_pixel(px, py).0;
+            let a = a as f32 / 255.0;
+            for (c, v) in sum.iter_mut().zip([r, g, b]) {
+                *c += v as f32 / 255.0 * a;
+            }
+            weight += a;
         }
     }
+
+    (weight > 0.0).then(|| sum.map(|c| c / weight))
 }
@@ -0,0 +1,207 @@
+use std::io::Cursor;
+
+use egor::{
+    app::Context,
+    input::{Input, KeyCode},
+    math::Vec2,
+    render::Color,
+};
+use glam::{IVec2, IVec3};
+use image::{ImageFormat, Rgba, RgbaImage};
+
+use crate::{fog::Explored, map::Map, ui::Screen};
+
+// the corner minimap, in screen pixels
+const MINIMAP_SIZE: f32 = 160.0;
+const MINIMAP_MARGIN: f32 = 10.0;
+const MINIMAP_SCALE: f32 = 4.0;
+
+// the full map zooms in steps of whole screen pixels per tile
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 16.0;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+
+// newly explored tiles are added to the texture at most this often, in seconds
+const REFRESH_INTERVAL: f32 = 0.5;
+
+// something to point out on the map, like a player or a portal
+pub struct Marker {
+    pub tile: IVec2,
+    pub color: Color,
+}
+
+// the explored tiles of one floor, a pixel per tile, so however much of the
+// map is in view it's drawn as a single quad
+struct ExploredTexture {
+    map: String,
+    floor: i32,
+    // how many tiles were explored when it was drawn
+    explored: usize,
+    // the tile of the top left pixel, and how many tiles across and down
+    origin: IVec2,
+    size: IVec2,
+    texture: usize,
+}
+
+// a minimap in the corner of the screen, or the whole map when opened with M
+pub struct Minimap {
+    full: bool,
+    // screen pixels per tile on the full map
+    zoom: f32,
+    texture: Option<ExploredTexture>,
+    refresh_timer: f32,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            full: false,
+            zoom: 4.0,
+            texture: None,
+            refresh_timer: 0.0,
+        }
+    }
+}
+
+impl Minimap {
+    pub fn update(&mut self, input: &Input) {
+        if input.key_pressed(KeyCode::KeyM) {
+            self.full = !self.full;
+        }
+
+        if self.full {
+            if input.key_pressed(KeyCode::Minus) {
+                self.zoom = (self.zoom / 2.0).max(MIN_ZOOM);
+            }
+            if input.key_pressed(KeyCode::Equal) {
+                self.zoom = (self.zoom * 2.0).min(MAX_ZOOM);
+            }
+        }
+    }
+
+    // redraws the explored texture when the player moves to another map or
+    // floor, or has explored more of this one
+    pub fn refresh(
+        &mut self,
+        ctx: &mut Context,
+        map_name: &str,
+        map: &Map,
+        explored: &Explored,
+        floor: i32,
+    ) {
+        self.refresh_timer += ctx.timer.delta;
+        let current = self
+            .texture
+            .as_ref()
+            .is_some_and(|t| t.map == map_name && t.floor == floor);
+        let explored_more = self
+            .texture
+            .as_ref()
+            .is_some_and(|t| t.explored != explored.len());
+        if current && (!explored_more || self.refresh_timer < REFRESH_INTERVAL) {
+            return;
+        }
+        self.refresh_timer = 0.0;
+
+        let (min, max) = explored
+            .tiles_on(floor)
+            .fold((IVec2::MAX, IVec2::MIN), |(min, max), t| {
+                (min.min(t), max.max(t))
+            });
+        // nothing explored yet still gets a pixel, to keep a texture around
+        let (origin, size) = match min.cmple(max).all() {
+            true => (min, max - min + IVec2::ONE),
+            false => (IVec2::ZERO, IVec2::ONE),
+        };
+
+        let mut image = RgbaImage::new(size.x as u32, size.y as u32);
+        for tile_pos in explored.tiles_on(floor) {
+            if let Some(color) = map.minimap_color(tile_pos, floor) {
+                let [r, g, b] = color.map(|c| (c * 255.0) as u8);
+                let pixel = (tile_pos - origin).as_uvec2();
+                image.put_pixel(pixel.x, pixel.y, Rgba([r, g, b, 255]));
+            }
+        }
+        let mut bytes = Vec::new();
+        if let Err(e) = image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png) {
+            eprintln!("Failed to draw the minimap: {}", e);
+            return;
+        }
+
+        let texture = match self.texture.take() {
+            Some(old) => {
+                ctx.graphics.update_texture(old.texture, &bytes);
+                old.texture
+            }
+            None => ctx.graphics.load_texture(&bytes),
+        };
+        self.texture = Some(ExploredTexture {
+            map: map_name.to_string(),
+            floor,
+            explored: explored.len(),
+            origin,
+            size,
+            texture,
+        });
+    }
+
+    // only explored tiles are shown, centered on `center`
+    pub fn draw(&self, ctx: &mut Context, screen: &Screen, center: IVec3, markers: &[Marker]) {
+        let (at, size, scale) = match self.full {
+            true => (Vec2::ZERO, screen.size, self.zoom),
+            false => (
+                Vec2::new(
+                    screen.size.x - MINIMAP_SIZE - MINIMAP_MARGIN,
+                    MINIMAP_MARGIN,
+                ),
+                Vec2::splat(MINIMAP_SIZE),
+                MINIMAP_SCALE,
+            ),
+        };
+
+        let cell = |tile_pos: IVec2| {
+            let offset = (tile_pos - center.truncate()).as_vec2() * scale;
+            at + size / 2.0 + offset - scale / 2.0
+        };
+        let inside = |min: Vec2| min.cmpge(at).all() && (min + scale).cmple(at + size).all();
+
+        screen.rect(ctx, at, size, Color::new(BACKGROUND));
+
+        // the tiles that fit whole inside the box, cut down to those in the
+        // texture, which is drawn with its UVs trimmed to match
+        if let Some(explored) = self.texture.as_ref().filter(|t| t.floor == center.z) {
+            let half = (size / scale / 2.0 - 0.5).floor().as_ivec2();
+            let first = (center.truncate() - half).max(explored.origin);
+            let last = (center.truncate() + half).min(explored.origin + explored.size - IVec2::ONE);
+
+            if first.cmple(last).all() {
+                let uv_min = (first - explored.origin).as_vec2() / explored.size.as_vec2();
+                let uv_max =
+                    (last + IVec2::ONE - explored.origin).as_vec2() / explored.size.as_vec2();
+                screen.image(
+                    ctx,
+                    cell(first),
+                    (last - first + IVec2::ONE).as_vec2() * scale,
+                    explored.texture,
+                    [
+                        [uv_min.x, uv_min.y],
+                        [uv_max.x, uv_min.y],
+                        [uv_max.x, uv_max.y],
+                        [uv_min.x, uv_max.y],
+                    ],
+                );
+            }
+        }
+
+        // markers stay visible at every zoom
+        let marker_size = scale.max(3.0);
+        for marker in markers {
+            if !inside(cell(marker.tile)) {
+                continue;
+            }
+            let min = cell(marker.tile) + (scale - marker_size) / 2.0;
+            screen.rect(ctx, min, Vec2::splat(marker_size), marker.color);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use dyrah_shared::NetId;
+use egor::{app::Context, math::Vec2, render::Color};
+
+use crate::ui::Screen;
+
+// all in screen pixels
+const NAME_SIZE: f32 = 12.0;
+const BAR_SIZE: Vec2 = Vec2::new(32.0, 4.0);
+const BAR_GAP: f32 = 1.0;
+const COMBAT_TEXT_SIZE: f32 = 14.0;
+const COMBAT_TEXT_RISE: f32 = 24.0;
+
+// how long floating combat text stays up, in seconds
+const COMBAT_TEXT_TIME: f32 = 1.0;
+
+// how long someone stays hostile after the last hit between us, in seconds
+const HOSTILE_TIME: f32 = 30.0;
+
+const OWN: [f32; 4] = [0.3, 1.0, 0.3, 1.0];
+const PARTY: [f32; 4] = [0.4, 0.7, 1.0, 1.0];
+const HOSTILE: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+const NPC: [f32; 4] = [1.0, 0.85, 0.3, 1.0];
+const NEUTRAL: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const HEAL: [f32; 4] = [0.3, 1.0, 0.3, 1.0];
+const MANA: [f32; 4] = [0.3, 0.5, 1.0, 1.0];
+
+// how an entity stands with the local player, which colors its name plate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Own,
+    Party,
+    Hostile,
+    Npc,
+    Neutral,
+}
+
+impl Relation {
+    fn color(&self) -> Color {
+        Color::new(match self {
+            Self::Own => OWN,
+            Self::Party => PARTY,
+            Self::Hostile => HOSTILE,
+            Self::Npc => NPC,
+            Self::Neutral => NEUTRAL,
+        })
+    }
+}
+
+// who's in our party and who we've traded blows with lately
+#[derive(Default)]
+pub struct Relations {
+    party: HashSet<NetId>,
+    // seconds each stays hostile for
+    hostile: HashMap<NetId, f32>,
+}
+
+impl Relations {
+    pub fn set_party(&mut self, members: Vec<NetId>) {
+        self.party = members.into_iter().collect();
+    }
+
+    // either of us hit the other
+    pub fn fought(&mut self, id: NetId) {
+        self.hostile.insert(id, HOSTILE_TIME);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.hostile.retain(|_, time| {
+            *time -= dt;
+            *time > 0.0
+        });
+    }
+
+    // anyone who isn't a player is an NPC
+    pub fn relation(&self, id: NetId, own_id: Option<NetId>, player: bool) -> Relation {
+        if !player {
+            Relation::Npc
+        } else if Some(id) == own_id {
+            Relation::Own
+        } else if self.party.contains(&id) {
+            Relation::Party
+        } else if self.hostile.contains_key(&id) {
+            Relation::Hostile
+        } else {
+            Relation::Neutral
+        }
+    }
+}
+
+pub struct NamePlate<'a> {
+    pub name: &'a str,
+    pub relation: Relation,
+    pub health: u8,
+    pub mana: Option<u8>,
+}
+
+impl NamePlate<'_> {
+    // `top` is the top middle of the entity's sprite, in the world
+    pub fn draw(&self, ctx: &mut Context, screen: &Screen, top: Vec2) {
+        let top = screen.world_to_screen(top);
+        let mana_at = top - Vec2::new(BAR_SIZE.x / 2.0, BAR_SIZE.y + BAR_GAP);
+        let health_at = match self.mana {
+            Some(_) => mana_at - Vec2::new(0.0, BAR_SIZE.y + BAR_GAP),
+            None => mana_at,
+        };
+        let color = self.relation.color();
+
+        screen.text(
+            ctx,
+            text_at(
+                self.name,
+                NAME_SIZE,
+                health_at + Vec2::new(BAR_SIZE.x / 2.0, -BAR_GAP),
+            ),
+            self.name,
+            NAME_SIZE,
+            color,
+        );
+        screen.bar(ctx, health_at, BAR_SIZE, self.health, color);
+        if let Some(mana) = self.mana {
+            screen.bar(ctx, mana_at, BAR_SIZE, mana, Color::new(MANA));
+        }
+    }
+}
+
+// there's no measuring text, so it's centered by a rough glyph width
+fn text_at(text: &str, size: f32, bottom_middle: Vec2) -> Vec2 {
+    let width = text.chars().count() as f32 * size * 0.5;
+    bottom_middle - Vec2::new(width / 2.0, size)
+}
+
+struct FloatingText {
+    // where it started, in the world
+    at: Vec2,
+    text: String,
+    color: [f32; 4],
+    age: f32,
+}
+
+// damage and healing numbers rising from whoever took them
+#[derive(Default)]
+pub struct CombatText {
+    texts: Vec<FloatingText>,
+}
+
+impl CombatText {
+    // `change` is in health points, negative for damage
+    pub fn push(&mut self, at: Vec2, change: i32) {
+        let (text, color) = match change {
+            0 => return,
+            c if c < 0 => (c.to_string(), HOSTILE),
+            c => (format!("+{}", c), HEAL),
+        };
+        self.texts.push(FloatingText {
+            at,
+            text,
+            color,
+            age: 0.0,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.texts.clear();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.texts.retain_mut(|t| {
+            t.age += dt;
+            t.age < COMBAT_TEXT_TIME
+        });
+    }
+
+    pub fn draw(&self, ctx: &mut Context, screen: &Screen) {
+        for t in &self.texts {
+            let progress = t.age / COMBAT_TEXT_TIME;
+            let at = screen.world_to_screen(t.at) - Vec2::new(0.0, progress * COMBAT_TEXT_RISE);
+            let [r, g, b, a] = t.color;
+
+            screen.text(
+                ctx,
+                text_at(&t.text, COMBAT_TEXT_SIZE, at),
+                &t.text,
+                COMBAT_TEXT_SIZE,
+                Color::new([r, g, b, a * (1.0 - progress)]),
+            );
+        }
+    }
+}
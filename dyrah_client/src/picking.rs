@@ -0,0 +1,40 @@
+use dyrah_shared::map::TiledMap;
+use egor::math::Vec2;
+use glam::IVec3;
+use secs::{Entity, World};
+
+use crate::components::{Floor, Sprite, WorldPos};
+
+// what's under the mouse cursor, worked out in world space so it holds up
+// however the camera is moved or zoomed
+pub struct Pick {
+    pub tile: IVec3,
+    // front-most first
+    pub entities: Vec<Entity>,
+}
+
+impl Pick {
+    // the cursor is on `floor`, where the player is looking
+    pub fn new(world: &World, tiled: &TiledMap, world_pos: Vec2, floor: i32) -> Self {
+        let mut entities = Vec::new();
+        world.query(
+            |entity, pos: &WorldPos, entity_floor: &Floor, spr: &Sprite| {
+                if entity_floor.z != floor {
+                    return;
+                }
+
+                let (min, max) = spr.bounds(pos.vec);
+                if world_pos.cmpge(min).all() && world_pos.cmplt(max).all() {
+                    entities.push((max.y, entity));
+                }
+            },
+        );
+        // whatever reaches further down is drawn in front
+        entities.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Self {
+            tile: tiled.world_to_tile(world_pos).extend(floor),
+            entities: entities.into_iter().map(|(_, e)| e).collect(),
+        }
+    }
+}
@@ -1,5 +1,33 @@
+use std::ops::Range;
+
+use dyrah_shared::map::TileFlip;
 use egor::math::Vec2;
 
+// the whole texture, for animations without any frames
+const FULL_UV: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+// mirrors a quad's UVs, given in TL, TR, BR, BL order
+pub fn flip_uv(mut uv: [[f32; 2]; 4], flip_x: bool, flip_y: bool) -> [[f32; 2]; 4] {
+    if flip_x {
+        uv.swap(0, 1); // TL <-> TR
+        uv.swap(3, 2); // BL <-> BR
+    }
+    if flip_y {
+        uv.swap(0, 3); // TL <-> BL
+        uv.swap(1, 2); // TR <-> BR
+    }
+
+    uv
+}
+
+// Tiled flips diagonally first, then horizontally and vertically
+pub fn flip_tile_uv(mut uv: [[f32; 2]; 4], flip: TileFlip) -> [[f32; 2]; 4] {
+    if flip.diagonal {
+        uv.swap(1, 3); // TR <-> BL
+    }
+    flip_uv(uv, flip.horizontal, flip.vertical)
+}
+
 #[derive(Debug)]
 struct Frame {
     uv_coords: [[f32; 2]; 4],
@@ -11,15 +39,26 @@ pub struct Animation {
     frames: Vec<Frame>,
     timer: f32,
     current: usize,
+    // one-shot animations finish after their last frame instead of starting over
+    looping: bool,
     flipped_x: bool,
     flipped_y: bool,
 }
 
 impl Animation {
     pub fn new(rows: usize, cols: usize, total: usize, dur: f32) -> Self {
-        let mut frames = Vec::with_capacity(total);
+        Self::from_frames(rows, cols, 0..total, dur)
+    }
+
+    // every frame of one row of the sheet
+    pub fn row(rows: usize, cols: usize, row: usize, dur: f32) -> Self {
+        Self::from_frames(rows, cols, row * cols..(row + 1) * cols, dur)
+    }
+
+    fn from_frames(rows: usize, cols: usize, indices: Range<usize>, dur: f32) -> Self {
+        let mut frames = Vec::with_capacity(indices.len());
         let (fw, fh) = (1.0 / cols as f32, 1.0 / rows as f32);
-        for i in 0..total {
+        for i in indices {
             let (x, y) = ((i % cols) as f32 * fw, (i / cols) as f32 * fh);
             frames.push(Frame {
                 uv_coords: [[x, y], [x + fw, y], [x + fw, y + fh], [x, y + fh]],
@@ -30,36 +69,42 @@ impl Animation {
             frames,
             timer: 0.0,
             current: 0,
+            looping: true,
             flipped_x: false,
             flipped_y: false,
         }
     }
 
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+
     pub fn update(&mut self, dt: f32) {
-        if self.frames.is_empty() {
+        if self.frames.is_empty() || self.is_finished() {
             return;
         }
 
         self.timer += dt;
         if self.timer >= self.frames[self.current].duration {
             self.timer = 0.0;
-            self.current = (self.current + 1) % self.frames.len();
+            if self.looping {
+                self.current = (self.current + 1) % self.frames.len();
+            } else {
+                self.current += 1;
+            }
         }
     }
 
-    pub fn frame(&self) -> [[f32; 2]; 4] {
-        let mut uv = self.frames[self.current].uv_coords;
-
-        if self.flipped_x {
-            uv.swap(0, 1); // TL <-> TR
-            uv.swap(3, 2); // BL <-> BR
-        }
-        if self.flipped_y {
-            uv.swap(0, 3); // TL <-> BL
-            uv.swap(1, 2); // TR <-> BR
-        }
+    // only ever true of one-shot animations, once past their last frame
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.frames.len()
+    }
 
-        uv
+    pub fn frame(&self) -> [[f32; 2]; 4] {
+        let current = self.current.min(self.frames.len().saturating_sub(1));
+        let uv = self.frames.get(current).map_or(FULL_UV, |f| f.uv_coords);
+        flip_uv(uv, self.flipped_x, self.flipped_y)
     }
     pub fn set_frame(&self, f: usize) -> [[f32; 2]; 4] {
         self.frames[f].uv_coords
@@ -86,3 +131,40 @@ impl Animation {
         offset
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // each corner's UV is told apart by its own coordinates
+    const TL: [f32; 2] = [0.0, 0.0];
+    const TR: [f32; 2] = [1.0, 0.0];
+    const BR: [f32; 2] = [1.0, 1.0];
+    const BL: [f32; 2] = [0.0, 1.0];
+    const UV: [[f32; 2]; 4] = [TL, TR, BR, BL];
+
+    fn flip(horizontal: bool, vertical: bool, diagonal: bool) -> TileFlip {
+        TileFlip {
+            horizontal,
+            vertical,
+            diagonal,
+        }
+    }
+
+    #[test]
+    fn flips_quads() {
+        assert_eq!(flip_uv(UV, false, false), UV);
+        assert_eq!(flip_uv(UV, true, false), [TR, TL, BL, BR]);
+        assert_eq!(flip_uv(UV, false, true), [BL, BR, TR, TL]);
+        assert_eq!(flip_uv(UV, true, true), [BR, BL, TL, TR]);
+    }
+
+    #[test]
+    fn rotates_tiles() {
+        assert_eq!(flip_tile_uv(UV, flip(false, false, true)), [TL, BL, BR, TR]);
+        // Tiled's rotations, clockwise
+        assert_eq!(flip_tile_uv(UV, flip(true, false, true)), [BL, TL, TR, BR]);
+        assert_eq!(flip_tile_uv(UV, flip(true, true, false)), [BR, BL, TL, TR]);
+        assert_eq!(flip_tile_uv(UV, flip(false, true, true)), [TR, BR, BL, TL]);
+    }
+}
@@ -0,0 +1,188 @@
+use dyrah_shared::{
+    NetId,
+    messages::{ClientMessage, EntityAction},
+};
+use egor::{
+    app::Context,
+    input::{Input, KeyCode, MouseButton},
+    math::Vec2,
+    render::Color,
+};
+
+use crate::ui::Screen;
+
+// all in screen pixels
+const FRAME_AT: Vec2 = Vec2::new(10.0, 10.0);
+const FRAME_SIZE: Vec2 = Vec2::new(180.0, 44.0);
+const MENU_WIDTH: f32 = 140.0;
+const MENU_ITEM_HEIGHT: f32 = 20.0;
+const TEXT_SIZE: f32 = 14.0;
+const PADDING: f32 = 6.0;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+const MENU_HOVER: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
+
+// what the target frame shows about an entity
+pub struct TargetInfo {
+    pub name: String,
+    pub health: u8,
+}
+
+struct ContextMenu {
+    target: NetId,
+    at: Vec2,
+}
+
+impl ContextMenu {
+    fn item_at(&self, screen_pos: Vec2) -> Option<EntityAction> {
+        let local = screen_pos - self.at;
+        if local.x < 0.0 || local.x >= MENU_WIDTH || local.y < 0.0 {
+            return None;
+        }
+        EntityAction::ALL
+            .get((local.y / MENU_ITEM_HEIGHT) as usize)
+            .copied()
+    }
+}
+
+// the entity the player has selected, by clicking it or cycling with Tab, and
+// the menu of what can be done to an entity on right-click
+#[derive(Default)]
+pub struct Targeting {
+    target: Option<NetId>,
+    menu: Option<ContextMenu>,
+}
+
+impl Targeting {
+    pub fn target(&self) -> Option<NetId> {
+        self.target
+    }
+
+    // the entity left our sight, or the game altogether
+    pub fn forget(&mut self, id: NetId) {
+        if self.target == Some(id) {
+            self.target = None;
+        }
+        if self.menu.as_ref().is_some_and(|m| m.target == id) {
+            self.menu = None;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.menu = None;
+    }
+
+    // `hovered` is the entity under the cursor and `nearby` the ones Tab cycles
+    // through, nearest first; returns whether the left click was used up here
+    pub fn update(
+        &mut self,
+        input: &Input,
+        hovered: Option<NetId>,
+        nearby: &[NetId],
+        messages: &mut Vec<ClientMessage>,
+    ) -> bool {
+        let mouse_pos: Vec2 = input.mouse_position().into();
+
+        if let Some(menu) = &self.menu {
+            if input.mouse_released(MouseButton::Left) {
+                if let Some(action) = menu.item_at(mouse_pos) {
+                    messages.push(ClientMessage::Interact {
+                        target: menu.target,
+                        action,
+                    });
+                    if action == EntityAction::Attack {
+                        self.target = Some(menu.target);
+                    }
+                }
+                self.menu = None;
+                return true;
+            }
+            if input.key_pressed(KeyCode::Escape) {
+                self.menu = None;
+                return false;
+            }
+        }
+
+        if input.mouse_released(MouseButton::Right) {
+            self.menu = hovered.map(|target| ContextMenu {
+                target,
+                at: mouse_pos,
+            });
+        }
+
+        if input.key_pressed(KeyCode::Tab) && !nearby.is_empty() {
+            let next = self
+                .target
+                .and_then(|t| nearby.iter().position(|&id| id == t))
+                .map_or(0, |i| (i + 1) % nearby.len());
+            self.target = Some(nearby[next]);
+        }
+
+        if input.key_pressed(KeyCode::Escape) && self.target.take().is_some() {
+            messages.push(ClientMessage::CancelActions);
+        }
+
+        match hovered {
+            Some(id) if input.mouse_released(MouseButton::Left) => {
+                self.target = Some(id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        screen: &Screen,
+        mouse_pos: Vec2,
+        info: impl Fn(NetId) -> Option<TargetInfo>,
+    ) {
+        if let Some(target) = self.target.and_then(&info) {
+            screen.rect(ctx, FRAME_AT, FRAME_SIZE, Color::new(BACKGROUND));
+            screen.text(
+                ctx,
+                FRAME_AT + PADDING,
+                &target.name,
+                TEXT_SIZE,
+                Color::WHITE,
+            );
+            screen.bar(
+                ctx,
+                FRAME_AT + Vec2::new(PADDING, FRAME_SIZE.y - PADDING - 8.0),
+                Vec2::new(FRAME_SIZE.x - PADDING * 2.0, 8.0),
+                target.health,
+                Color::GREEN,
+            );
+        }
+
+        if let Some(menu) = &self.menu {
+            let size = Vec2::new(
+                MENU_WIDTH,
+                MENU_ITEM_HEIGHT * EntityAction::ALL.len() as f32,
+            );
+            screen.rect(ctx, menu.at, size, Color::new(BACKGROUND));
+
+            let hovered = menu.item_at(mouse_pos);
+            for (i, action) in EntityAction::ALL.into_iter().enumerate() {
+                let at = menu.at + Vec2::new(0.0, i as f32 * MENU_ITEM_HEIGHT);
+                if hovered == Some(action) {
+                    screen.rect(
+                        ctx,
+                        at,
+                        Vec2::new(MENU_WIDTH, MENU_ITEM_HEIGHT),
+                        Color::new(MENU_HOVER),
+                    );
+                }
+                screen.text(
+                    ctx,
+                    at + Vec2::new(PADDING, (MENU_ITEM_HEIGHT - TEXT_SIZE) / 2.0),
+                    action.label(),
+                    TEXT_SIZE,
+                    Color::WHITE,
+                );
+            }
+        }
+    }
+}
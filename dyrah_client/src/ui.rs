@@ -0,0 +1,68 @@
+use egor::{app::Context, math::Vec2, render::Color};
+
+use crate::map::View;
+
+const BAR_EMPTY: [f32; 4] = [0.1, 0.1, 0.1, 0.8];
+
+// the HUD is laid out in screen pixels but drawn through the world camera,
+// so it stays put however the camera moves or zooms
+pub struct Screen {
+    view: View,
+    pub size: Vec2,
+}
+
+impl Screen {
+    pub fn new(view: View, size: Vec2) -> Self {
+        Self { view, size }
+    }
+
+    fn to_world(&self, screen_pos: Vec2) -> Vec2 {
+        self.view.screen_to_world(screen_pos, self.size)
+    }
+
+    // where something in the world shows up on screen
+    pub fn world_to_screen(&self, world_pos: Vec2) -> Vec2 {
+        self.view.world_to_screen(world_pos, self.size)
+    }
+
+    pub fn rect(&self, ctx: &mut Context, at: Vec2, size: Vec2, color: Color) {
+        let min = self.to_world(at);
+        let max = self.to_world(at + size);
+        ctx.graphics.rect().at(min).size(max - min).color(color);
+    }
+
+    pub fn image(
+        &self,
+        ctx: &mut Context,
+        at: Vec2,
+        size: Vec2,
+        texture: usize,
+        uv: [[f32; 2]; 4],
+    ) {
+        let min = self.to_world(at);
+        let max = self.to_world(at + size);
+        ctx.graphics
+            .rect()
+            .at(min)
+            .size(max - min)
+            .texture(texture)
+            .uv(uv);
+    }
+
+    // a bar filled from the left, like health
+    pub fn bar(&self, ctx: &mut Context, at: Vec2, size: Vec2, percent: u8, color: Color) {
+        let filled = size * Vec2::new(percent.min(100) as f32 / 100.0, 1.0);
+        self.rect(ctx, at, size, Color::new(BAR_EMPTY));
+        self.rect(ctx, at, filled, color);
+    }
+
+    // `size` is the text height in screen pixels
+    pub fn text(&self, ctx: &mut Context, at: Vec2, text: &str, size: f32, color: Color) {
+        let scale = (self.view.max.x - self.view.min.x) / self.size.x;
+        ctx.graphics
+            .text(text)
+            .at(self.to_world(at))
+            .size(size * scale)
+            .color(color);
+    }
+}
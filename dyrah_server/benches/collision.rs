@@ -0,0 +1,132 @@
+use std::{collections::HashMap, hint::black_box};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use dyrah_server::{
+    components::{Collider, MapId, TilePos},
+    map::CollisionGrid,
+};
+use dyrah_shared::map::TiledMap;
+use glam::{IVec2, IVec3};
+use secs::{Entity, World};
+
+const MAP_SIZE: u32 = 500;
+const ENTITIES: usize = 1000;
+
+// a square map with ground everywhere and a scattering of colliders
+fn generate_map() -> TiledMap {
+    let tiles = (MAP_SIZE * MAP_SIZE) as usize;
+    let colliders: Vec<u32> = (0..tiles)
+        .map(|i| if i % 7 == 0 || i % 11 == 0 { 2 } else { 0 })
+        .collect();
+
+    let json = format!(
+        r#"{{
+            "width": {size}, "height": {size}, "tilewidth": 32, "tileheight": 32,
+            "layers": [
+                {{ "name": "floor", "visible": true, "width": {size}, "height": {size}, "data": {floor:?} }},
+                {{ "name": "colliders", "visible": true, "width": {size}, "height": {size}, "data": {colliders:?} }}
+            ],
+            "tilesets": [{{ "firstgid": 1, "image": "bench.png", "tilecount": 2, "tilewidth": 32, "tileheight": 32 }}]
+        }}"#,
+        size = MAP_SIZE,
+        floor = vec![1; tiles],
+        colliders = colliders,
+    );
+
+    TiledMap::parse(&json).expect("Generated map should be valid")
+}
+
+fn spawn_entities(world: &mut World) -> Vec<(Entity, IVec3)> {
+    (0..ENTITIES)
+        .map(|i| {
+            let pos = IVec3::new((i * 13) as i32 % 400, (i * 7) as i32 % 400, 0);
+            let entity = world.spawn((
+                MapId {
+                    name: "bench".to_string(),
+                },
+                TilePos { vec: pos },
+                Collider,
+            ));
+            (entity, pos)
+        })
+        .collect()
+}
+
+// the old `CollisionGrid::update`, run every tick before the grid was kept
+// up to date as entities moved: each floor's walk grid was cleared and
+// rescanned from the map, then every collider on the map stamped on top
+fn full_rebuild(floors: &mut HashMap<i32, Vec<bool>>, tiled: &TiledMap, world: &World) {
+    let (width, height) = (tiled.width as usize, tiled.height as usize);
+
+    for (&z, walk) in floors.iter_mut() {
+        walk.fill(false);
+
+        for y in 0..height {
+            for x in 0..width {
+                let tile_pos = IVec3::new(x as i32, y as i32, z);
+                if !tiled.is_walkable("colliders", tile_pos) {
+                    walk[y * width + x] = true;
+                }
+            }
+        }
+    }
+
+    world.query(|_, _: &Collider, map_id: &MapId, tile_pos: &TilePos| {
+        if map_id.name != "bench" {
+            return;
+        }
+
+        let (x, y) = (tile_pos.vec.x as usize, tile_pos.vec.y as usize);
+        if let Some(walk) = floors.get_mut(&tile_pos.vec.z)
+            && x < width
+            && y < height
+        {
+            walk[y * width + x] = true;
+        }
+    });
+}
+
+fn collision(c: &mut Criterion) {
+    let tiled = generate_map();
+    let mut world = World::default();
+    let entities = spawn_entities(&mut world);
+
+    c.bench_function("full rebuild per tick", |b| {
+        let mut floors: HashMap<i32, Vec<bool>> = tiled
+            .floors()
+            .into_iter()
+            .map(|z| (z, vec![false; (MAP_SIZE * MAP_SIZE) as usize]))
+            .collect();
+        b.iter(|| full_rebuild(black_box(&mut floors), &tiled, &world));
+    });
+
+    c.bench_function("incremental moves per tick", |b| {
+        let mut grid = CollisionGrid::new(&tiled);
+        for &(entity, pos) in &entities {
+            grid.occupy(entity, pos, true);
+        }
+
+        // every entity steps back and forth, the worst case for a single tick
+        let mut step = IVec2::X;
+        b.iter(|| {
+            for &(entity, pos) in &entities {
+                let from = if step == IVec2::X {
+                    pos
+                } else {
+                    pos + IVec3::X
+                };
+                let to = from + step.extend(0);
+                grid.move_occupant(entity, from, to);
+            }
+            step = -step;
+            black_box(grid.is_walkable(IVec3::new(1, 1, 0)))
+        });
+    });
+
+    c.bench_function("collision grid build", |b| {
+        b.iter(|| CollisionGrid::new(black_box(&tiled)));
+    });
+}
+
+criterion_group!(benches, collision);
+criterion_main!(benches);
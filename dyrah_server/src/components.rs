@@ -1,14 +1,347 @@
-use glam::IVec2;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use dyrah_shared::{NetId, conditions::ConditionKind, spells::CooldownGroup};
+use glam::{IVec2, IVec3};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct TilePos {
-    pub vec: IVec2,
+    pub vec: IVec3,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct TargetTilePos {
-    pub vec: IVec2,
+    pub vec: IVec3,
 }
 
 pub struct Collider;
+
+#[derive(Debug, Default)]
+pub struct MapId {
+    pub name: String,
+}
+
+// chunks of an infinite map the player's client has been sent
+#[derive(Debug, Default)]
+pub struct LoadedChunks {
+    pub chunks: HashSet<IVec2>,
+}
+
+// other players the player's client has been told about, which are the ones
+// in its line of sight
+#[derive(Debug, Default)]
+pub struct VisiblePlayers {
+    pub ids: HashSet<NetId>,
+}
+
+#[derive(Debug)]
+pub struct Name {
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Self { current: max, max }
+    }
+
+    // what clients are told, so exact numbers stay on the server
+    pub fn percent(&self) -> u8 {
+        (self.current * 100).div_ceil(self.max.max(1)) as u8
+    }
+}
+
+#[derive(Debug)]
+pub struct Mana {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Mana {
+    pub fn new(max: u32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn percent(&self) -> u8 {
+        (self.current * 100).div_ceil(self.max.max(1)) as u8
+    }
+}
+
+// the player's level, spell cooldowns left in seconds and rune charges
+#[derive(Debug)]
+pub struct Magic {
+    pub level: u32,
+    pub cooldowns: HashMap<CooldownGroup, f32>,
+    pub runes: BTreeMap<String, u32>,
+}
+
+impl Magic {
+    pub fn is_exhausted(&self, group: CooldownGroup) -> bool {
+        self.cooldowns.contains_key(&group)
+    }
+
+    // casting a spell holds back its whole group
+    pub fn start_cooldown(&mut self, group: CooldownGroup, seconds: f32) {
+        self.cooldowns.insert(group, seconds);
+    }
+
+    pub fn update_cooldowns(&mut self, dt: f32) {
+        self.cooldowns.retain(|_, time| {
+            *time -= dt;
+            *time > 0.0
+        });
+    }
+
+    pub fn add_runes(&mut self, rune: &str, charges: u32) {
+        *self.runes.entry(rune.to_string()).or_default() += charges;
+    }
+
+    // returns whether there was a charge to use; used up runes are dropped
+    pub fn use_rune(&mut self, rune: &str) -> bool {
+        let Some(charges) = self.runes.get_mut(rune) else {
+            return false;
+        };
+        *charges -= 1;
+        if *charges == 0 {
+            self.runes.remove(rune);
+        }
+        true
+    }
+}
+
+// who the player is attacking and following, each checked every tick, and
+// how long until they can take another step
+#[derive(Debug, Default)]
+pub struct Actions {
+    pub attacking: Option<NetId>,
+    pub following: Option<NetId>,
+    pub attack_cooldown: f32,
+    pub follow_cooldown: f32,
+    pub step_cooldown: f32,
+}
+
+// conditions never last longer than this, however much they're extended
+const MAX_CONDITION_TIME: f32 = 60.0;
+
+// what happens when a player gets a condition they already have
+enum Stacking {
+    // the longer and stronger of the two
+    Refresh,
+    // the time adds up
+    Extend,
+}
+
+fn stacking(kind: ConditionKind) -> Stacking {
+    match kind {
+        ConditionKind::Poison => Stacking::Extend,
+        _ => Stacking::Refresh,
+    }
+}
+
+// seconds between the hurting or healing of the conditions that do either
+pub fn tick_interval(kind: ConditionKind) -> Option<f32> {
+    match kind {
+        ConditionKind::Poison | ConditionKind::Burning => Some(2.0),
+        ConditionKind::Electrified | ConditionKind::Regeneration => Some(1.0),
+        ConditionKind::Paralyze | ConditionKind::Haste | ConditionKind::Invisibility => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct Condition {
+    pub kind: ConditionKind,
+    // seconds left
+    pub remaining: f32,
+    // seconds until the next tick
+    pub tick_timer: f32,
+    // per tick
+    pub power: u32,
+    // whoever gave it, if anyone
+    pub source: Option<NetId>,
+}
+
+impl Condition {
+    pub fn new(kind: ConditionKind, duration: f32, power: u32, source: Option<NetId>) -> Self {
+        Self {
+            kind,
+            remaining: duration.min(MAX_CONDITION_TIME),
+            tick_timer: tick_interval(kind).unwrap_or(0.0),
+            power,
+            source,
+        }
+    }
+}
+
+// a condition hurting or healing, as its kind, power and source
+pub type ConditionTick = (ConditionKind, u32, Option<NetId>);
+
+#[derive(Debug, Default)]
+pub struct Conditions {
+    pub active: Vec<Condition>,
+}
+
+impl Conditions {
+    pub fn kinds(&self) -> Vec<ConditionKind> {
+        self.active.iter().map(|c| c.kind).collect()
+    }
+
+    pub fn has(&self, kind: ConditionKind) -> bool {
+        self.active.iter().any(|c| c.kind == kind)
+    }
+
+    // returns whether the player's set of conditions changed
+    pub fn add(&mut self, condition: Condition) -> bool {
+        // haste and paralysis cancel each other out
+        let opposite = match condition.kind {
+            ConditionKind::Haste => Some(ConditionKind::Paralyze),
+            ConditionKind::Paralyze => Some(ConditionKind::Haste),
+            _ => None,
+        };
+        let count = self.active.len();
+        self.active.retain(|c| Some(c.kind) != opposite);
+        let cancelled = self.active.len() != count;
+
+        let Some(active) = self.active.iter_mut().find(|c| c.kind == condition.kind) else {
+            self.active.push(condition);
+            return true;
+        };
+        active.remaining = match stacking(condition.kind) {
+            Stacking::Refresh => active.remaining.max(condition.remaining),
+            Stacking::Extend => (active.remaining + condition.remaining).min(MAX_CONDITION_TIME),
+        };
+        active.power = active.power.max(condition.power);
+        active.source = condition.source.or(active.source);
+        cancelled
+    }
+
+    // returns the conditions that hurt or heal this time and the kinds that
+    // ran out
+    pub fn update(&mut self, dt: f32) -> (Vec<ConditionTick>, Vec<ConditionKind>) {
+        let mut ticks = Vec::new();
+        for condition in &mut self.active {
+            condition.remaining -= dt;
+            let Some(interval) = tick_interval(condition.kind) else {
+                continue;
+            };
+            condition.tick_timer -= dt;
+            if condition.tick_timer <= 0.0 {
+                condition.tick_timer += interval;
+                ticks.push((condition.kind, condition.power, condition.source));
+            }
+        }
+
+        let expired = self
+            .active
+            .iter()
+            .filter(|c| c.remaining <= 0.0)
+            .map(|c| c.kind)
+            .collect();
+        self.active.retain(|c| c.remaining > 0.0);
+        (ticks, expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions(active: impl IntoIterator<Item = Condition>) -> Conditions {
+        let mut conditions = Conditions::default();
+        for condition in active {
+            conditions.add(condition);
+        }
+        conditions
+    }
+
+    #[test]
+    fn refreshing_keeps_the_longer_and_stronger() {
+        let mut conditions = conditions([Condition::new(ConditionKind::Burning, 10.0, 5, None)]);
+        let changed = conditions.add(Condition::new(ConditionKind::Burning, 4.0, 8, Some(3)));
+
+        assert!(!changed);
+        let burning = &conditions.active[0];
+        assert_eq!(conditions.active.len(), 1);
+        assert_eq!(burning.remaining, 10.0);
+        assert_eq!(burning.power, 8);
+        assert_eq!(burning.source, Some(3));
+    }
+
+    #[test]
+    fn poison_stacks_up_to_the_limit() {
+        let mut conditions = conditions([Condition::new(ConditionKind::Poison, 20.0, 2, None)]);
+        conditions.add(Condition::new(ConditionKind::Poison, 15.0, 2, None));
+        assert_eq!(conditions.active[0].remaining, 35.0);
+
+        conditions.add(Condition::new(ConditionKind::Poison, 40.0, 2, None));
+        assert_eq!(conditions.active[0].remaining, MAX_CONDITION_TIME);
+    }
+
+    #[test]
+    fn haste_and_paralysis_cancel_out() {
+        let mut conditions = conditions([Condition::new(ConditionKind::Haste, 10.0, 0, None)]);
+        assert!(conditions.add(Condition::new(ConditionKind::Paralyze, 5.0, 0, None)));
+        assert_eq!(conditions.kinds(), [ConditionKind::Paralyze]);
+    }
+
+    #[test]
+    fn conditions_tick_and_run_out() {
+        let mut conditions = conditions([
+            Condition::new(ConditionKind::Electrified, 2.5, 4, Some(7)),
+            Condition::new(ConditionKind::Haste, 1.5, 0, None),
+        ]);
+
+        let (ticks, expired) = conditions.update(1.0);
+        assert_eq!(ticks, [(ConditionKind::Electrified, 4, Some(7))]);
+        assert!(expired.is_empty());
+
+        let (ticks, expired) = conditions.update(1.0);
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(expired, [ConditionKind::Haste]);
+
+        let (ticks, expired) = conditions.update(1.0);
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(expired, [ConditionKind::Electrified]);
+        assert!(conditions.active.is_empty());
+    }
+
+    fn magic() -> Magic {
+        Magic {
+            level: 1,
+            cooldowns: HashMap::new(),
+            runes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn cooldowns_hold_back_their_group() {
+        let mut magic = magic();
+        magic.start_cooldown(CooldownGroup::Attack, 2.0);
+        assert!(magic.is_exhausted(CooldownGroup::Attack));
+        assert!(!magic.is_exhausted(CooldownGroup::Healing));
+
+        magic.update_cooldowns(1.5);
+        assert!(magic.is_exhausted(CooldownGroup::Attack));
+        magic.update_cooldowns(0.5);
+        assert!(!magic.is_exhausted(CooldownGroup::Attack));
+    }
+
+    #[test]
+    fn runes_run_out() {
+        let mut magic = magic();
+        assert!(!magic.use_rune("Great Fireball Rune"));
+
+        magic.add_runes("Great Fireball Rune", 1);
+        magic.add_runes("Great Fireball Rune", 1);
+        assert_eq!(magic.runes["Great Fireball Rune"], 2);
+
+        assert!(magic.use_rune("Great Fireball Rune"));
+        assert!(magic.use_rune("Great Fireball Rune"));
+        assert!(!magic.runes.contains_key("Great Fireball Rune"));
+        assert!(!magic.use_rune("Great Fireball Rune"));
+    }
+}
@@ -1,6 +1,12 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
 
 use bincode::{deserialize, serialize};
+use glam::{IVec2, IVec3, Vec2};
 use secs::{Entity, World};
 use wrym::{
     server::{Server, ServerConfig, ServerEvent},
@@ -9,113 +15,1367 @@ use wrym::{
 
 use dyrah_shared::{
     NetId,
+    clock::DAY_LENGTH,
     components::Player,
-    messages::{ClientMessage, ServerMessage},
+    conditions::{ConditionKind, step_interval},
+    is_valid_name,
+    map::{DEFAULT_MAP, chunk_of},
+    messages::{ClientInput, ClientMessage, EffectKind, EntityAction, ServerMessage},
+    spells::{Area, Spell, SpellEffect, Spells},
 };
 
-use crate::{
-    components::{Collider, TargetTilePos, TilePos},
-    map::{CollisionGrid, Map},
+use dyrah_server::{
+    components::{
+        Actions, Collider, Condition, Conditions, Health, LoadedChunks, Magic, Mana, MapId, Name,
+        TargetTilePos, TilePos, VisiblePlayers,
+    },
+    map::Maps,
+    storage::{CharacterData, Storage},
 };
 
+// how many bytes of a map file go in each download message, and how many of
+// those each download gets per tick so big maps don't flood the connection
+const MAP_PART_SIZE: usize = 1024;
+const MAP_PARTS_PER_TICK: usize = 32;
+
+// a client waits this long after a map download before it gets another, in
+// seconds, since each is the whole map; asking sooner waits its turn
+const MAP_DOWNLOAD_COOLDOWN: f32 = 5.0;
+
+// how many chunks around a player of an infinite map are streamed to them
+const STREAM_RADIUS: i32 = 2;
+
+// how often clients are reminded of the time, in seconds
+const CLOCK_SYNC: f32 = 10.0;
+
+const SPELLS_PATH: &str = "assets/data/spells.json";
+
+const PLAYER_HEALTH: u32 = 100;
+const PLAYER_MANA: u32 = 100;
+
+// mana comes back a little at a time
+const MANA_REGEN: u32 = 2;
+const MANA_REGEN_INTERVAL: f32 = 2.0;
+
+// longer is ignored rather than cut off
+const MAX_SAY_LENGTH: usize = 200;
+
+// melee attacks, for now the only kind
+const ATTACK_DAMAGE: u32 = 10;
+const ATTACK_INTERVAL: f32 = 2.0;
+
+// how often a following player takes a step
+const FOLLOW_INTERVAL: f32 = 0.25;
+
+// how early a step may come, for network jitter
+const STEP_SLACK: f32 = 0.05;
+
+// how long effects on tiles play, in seconds, and how fast thrown ones fly,
+// in tiles per second
+const EFFECT_DURATION: f32 = 0.6;
+const PROJECTILE_SPEED: f32 = 12.0;
+
 pub struct Game {
     server: Server<Transport>,
     lobby: HashMap<NetId, Entity>,
     world: World,
-    collision_grid: CollisionGrid,
-    map: Map,
+    maps: Maps,
+    spells: Spells,
+    storage: Storage,
+    shutdown_timer: Option<f32>,
+    // seconds into the current day
+    time: f32,
+    clock_sync_timer: f32,
+    // each party member's leader, who started the party
+    parties: HashMap<NetId, NetId>,
+    // who invited whom, until it's accepted by inviting them back
+    party_invites: HashSet<(NetId, NetId)>,
+    mana_regen_timer: f32,
+    // seconds until each client may download another map, and the map it
+    // asked for in the meantime
+    download_cooldowns: HashMap<NetId, f32>,
+    queued_downloads: HashMap<NetId, String>,
+    uploads: HashMap<NetId, Upload>,
+}
+
+// a map download under way, up to the next part to send
+struct Upload {
+    map: String,
+    file: usize,
+    offset: usize,
 }
 
 impl Game {
-    pub fn new() -> Self {
-        let map = Map::new("assets/map.json");
+    pub fn new() -> Result<Self, String> {
+        let maps = Maps::load("assets");
+        let Some(map) = maps.get(DEFAULT_MAP) else {
+            return Err(format!("Default map {} is missing or broken", DEFAULT_MAP));
+        };
+        // new and respawning players start there
+        if map.get_spawn("player").is_none() {
+            return Err(format!("Default map {} has no player spawn", DEFAULT_MAP));
+        }
+        let spells = Spells::load(Path::new(SPELLS_PATH))
+            .map_err(|e| format!("Failed to load spells from {}: {}", SPELLS_PATH, e))?;
 
-        Self {
+        Ok(Self {
             server: Server::new(Transport::new("127.0.0.1:8080"), ServerConfig::default()),
             lobby: HashMap::new(),
             world: World::default(),
-            collision_grid: CollisionGrid::new(&map),
-            map,
+            maps,
+            spells,
+            storage: Storage::new("saves"),
+            shutdown_timer: None,
+            // servers start in the morning
+            time: DAY_LENGTH * 8.0 / 24.0,
+            clock_sync_timer: 0.0,
+            parties: HashMap::new(),
+            party_invites: HashSet::new(),
+            mana_regen_timer: 0.0,
+            download_cooldowns: HashMap::new(),
+            queued_downloads: HashMap::new(),
+            uploads: HashMap::new(),
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.shutdown_timer.is_none_or(|t| t > 0.0)
+    }
+
+    pub fn begin_shutdown(&mut self, grace: f32) {
+        if self.shutdown_timer.is_some() {
+            return;
         }
+
+        println!("Shutting down in {} seconds..", grace);
+        self.shutdown_timer = Some(grace);
+
+        let msg = ServerMessage::ShutdownWarning {
+            seconds: grace.ceil() as u32,
+        };
+        self.server
+            .broadcast_reliable(&serialize(&msg).unwrap(), true);
     }
 
-    pub fn handle_events(&mut self) {
-        while let Some(event) = self.server.recv_event() {
-            match event {
-                ServerEvent::ClientConnected(id) => {
-                    let addr = self.server.client_addr(id).unwrap();
-                    println!("Client {} connected from {}", id, addr);
+    pub fn shutdown(&mut self) {
+        println!("Saving {} players..", self.lobby.len());
+
+        for &id in self.lobby.keys() {
+            self.save_player(id);
+        }
+
+        let msg = ServerMessage::Disconnected {
+            reason: "Server is shutting down".to_string(),
+        };
+        self.server
+            .broadcast_reliable(&serialize(&msg).unwrap(), true);
+
+        // give the transport a moment to flush reliable packets
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            self.server.poll();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        println!("Server stopped.");
+    }
+
+    fn save_player(&self, id: NetId) {
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        let map_id = self.world.get::<MapId>(player).unwrap();
+        let tile_pos = self.world.get::<TilePos>(player).unwrap();
+        let magic = self.world.get::<Magic>(player).unwrap();
+        let name = self.world.get::<Name>(player).unwrap();
+        let data = CharacterData {
+            map: map_id.name.clone(),
+            tile_pos: tile_pos.vec.truncate(),
+            floor: tile_pos.vec.z,
+            level: magic.level,
+            runes: magic.runes.clone(),
+        };
+
+        if let Err(e) = self.storage.save(&name.name, &data) {
+            eprintln!("Failed to save {}: {}", name.name, e);
+        }
+    }
+
+    fn player_map(&self, player: Entity) -> String {
+        self.world.get::<MapId>(player).unwrap().name.clone()
+    }
+
+    fn spawn_message(&self, id: NetId, position: Vec2) -> ServerMessage {
+        let player = self.lobby[&id];
+        ServerMessage::PlayerSpawned {
+            id,
+            name: self.world.get::<Name>(player).unwrap().name.clone(),
+            position,
+            floor: self.world.get::<TilePos>(player).unwrap().vec.z,
+            health: self.world.get::<Health>(player).unwrap().percent(),
+            mana: self.world.get::<Mana>(player).unwrap().percent(),
+            conditions: self.world.get::<Conditions>(player).unwrap().kinds(),
+        }
+    }
 
-                    // sync existing players on new clients
-                    for (&other_id, &player) in &self.lobby {
-                        let target_pos = self.world.get::<TargetTilePos>(player).unwrap();
-                        let msg = ServerMessage::PlayerSpawned {
-                            id: other_id,
-                            position: self.map.tiled.tile_to_world(target_pos.vec),
-                        };
+    fn send_reliable(&self, id: NetId, msg: &ServerMessage) {
+        let Some(addr) = self.server.client_addr(id) else {
+            return;
+        };
+        self.server
+            .send_reliable_to(&addr, &serialize(msg).unwrap(), true);
+    }
+
+    fn send_text(&self, id: NetId, text: String) {
+        self.send_reliable(id, &ServerMessage::TextMessage { text });
+    }
 
-                        self.server
-                            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+    // sends to the player and everyone who can see them
+    fn send_to_viewers(&self, id: NetId, msg: &ServerMessage, reliable: bool) {
+        let bytes = serialize(msg).unwrap();
+
+        for (&other_id, &other) in &self.lobby {
+            let visible = self.world.get::<VisiblePlayers>(other).unwrap();
+            if other_id != id && !visible.ids.contains(&id) {
+                continue;
+            }
+
+            if let Some(addr) = self.server.client_addr(other_id) {
+                if reliable {
+                    self.server.send_reliable_to(&addr, &bytes, true);
+                } else {
+                    self.server.send_to(&addr, &bytes);
+                }
+            }
+        }
+    }
+
+    // effects only matter as they happen, so they're sent unreliably
+    fn send_effect(
+        &self,
+        id: NetId,
+        effect: EffectKind,
+        tiles: Vec<IVec2>,
+        floor: i32,
+        delay: f32,
+    ) {
+        let msg = ServerMessage::TileEffect {
+            effect,
+            tiles,
+            floor,
+            duration: EFFECT_DURATION,
+            delay,
+        };
+        self.send_to_viewers(id, &msg, false);
+    }
+
+    // an effect on the tile the player stands on
+    fn send_effect_on(&self, id: NetId, effect: EffectKind) {
+        let tile_pos = self.world.get::<TilePos>(self.lobby[&id]).unwrap().vec;
+        self.send_effect(id, effect, vec![tile_pos.truncate()], tile_pos.z, 0.0);
+    }
+
+    // players are only told about the players in their line of sight, so
+    // each move can bring others into view or take them out of it
+    fn update_visibility(&self, map_name: &str) {
+        let map = self.maps.get(map_name).unwrap();
+        let players: Vec<(NetId, Entity, IVec3, bool)> = self
+            .lobby
+            .iter()
+            .filter(|&(_, &p)| self.player_map(p) == map_name)
+            .map(|(&id, &p)| {
+                let conditions = self.world.get::<Conditions>(p).unwrap();
+                let invisible = conditions.has(ConditionKind::Invisibility);
+                (id, p, self.world.get::<TilePos>(p).unwrap().vec, invisible)
+            })
+            .collect();
+
+        for &(id, player, tile_pos, _) in &players {
+            let Some(addr) = self.server.client_addr(id) else {
+                continue;
+            };
+            let mut visible = self.world.get_mut::<VisiblePlayers>(player).unwrap();
+
+            for &(other_id, _, other_pos, invisible) in &players {
+                if other_id == id {
+                    continue;
+                }
+
+                // the invisible are only seen by their party
+                let seen = (!invisible || self.same_party(id, other_id))
+                    && map.can_see(tile_pos, other_pos);
+                let msg = match (seen, visible.ids.contains(&other_id)) {
+                    (true, false) => {
+                        visible.ids.insert(other_id);
+                        self.spawn_message(other_id, map.tiled.tile_to_world(other_pos.truncate()))
                     }
+                    (false, true) => {
+                        visible.ids.remove(&other_id);
+                        ServerMessage::PlayerDespawned { id: other_id }
+                    }
+                    _ => continue,
+                };
+                self.server
+                    .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+            }
+        }
+    }
+
+    // the player is leaving their map, so whoever could see them is told
+    fn hide_player(&self, id: NetId) {
+        let bytes = serialize(&ServerMessage::PlayerDespawned { id }).unwrap();
+
+        for (&other_id, &other) in &self.lobby {
+            let mut visible = self.world.get_mut::<VisiblePlayers>(other).unwrap();
+            if other_id == id || !visible.ids.remove(&id) {
+                continue;
+            }
+
+            if let Some(addr) = self.server.client_addr(other_id) {
+                self.server.send_reliable_to(&addr, &bytes, true);
+            }
+        }
+    }
 
-                    let spawn_pos = self.map.get_spawn("player").unwrap();
-                    let player = self.world.spawn((
-                        Player,
-                        TilePos { vec: spawn_pos },
-                        TargetTilePos { vec: spawn_pos },
-                        Collider,
-                    ));
-                    self.lobby.insert(id, player);
-
-                    println!(
-                        "Spawned player {} at tile: {:?}, world: {:?}",
-                        id,
-                        spawn_pos,
-                        self.map.tiled.tile_to_world(spawn_pos)
-                    );
-
-                    let msg = ServerMessage::PlayerSpawned {
-                        id,
-                        position: self.map.tiled.tile_to_world(spawn_pos),
+    fn leave_map(&mut self, player: Entity) {
+        let map_name = self.player_map(player);
+        let tile_pos = self.world.get::<TilePos>(player).unwrap().vec;
+
+        if let Some(map) = self.maps.get_mut(&map_name) {
+            map.collision_grid.vacate(player, tile_pos);
+        }
+    }
+
+    // infinite maps are sent to clients a few chunks around the player at a
+    // time, and chunks left well behind are dropped again
+    fn stream_chunks(&mut self, id: NetId) {
+        let player = self.lobby[&id];
+        let map_name = self.player_map(player);
+        let map = self.maps.get(&map_name).unwrap();
+        if !map.tiled.infinite {
+            return;
+        }
+
+        let addr = self.server.client_addr(id).unwrap();
+        let tile_pos = self.world.get::<TilePos>(player).unwrap().vec;
+        let (center, _) = chunk_of(tile_pos.truncate());
+        let mut loaded = self.world.get_mut::<LoadedChunks>(player).unwrap();
+
+        let far: Vec<IVec2> = loaded
+            .chunks
+            .iter()
+            .copied()
+            .filter(|&c| (c - center).abs().max_element() > STREAM_RADIUS + 1)
+            .collect();
+        for chunk in far {
+            loaded.chunks.remove(&chunk);
+
+            let msg = ServerMessage::MapChunkUnloaded {
+                map: map_name.clone(),
+                chunk,
+            };
+            self.server
+                .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+        }
+
+        for y in -STREAM_RADIUS..=STREAM_RADIUS {
+            for x in -STREAM_RADIUS..=STREAM_RADIUS {
+                let chunk = center + IVec2::new(x, y);
+                if !loaded.chunks.insert(chunk) {
+                    continue;
+                }
+
+                let layers = map.tiled.read_chunk(chunk);
+                if layers.is_empty() {
+                    continue;
+                }
+
+                let msg = ServerMessage::MapChunk {
+                    map: map_name.clone(),
+                    chunk,
+                    layers,
+                };
+                self.server
+                    .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+            }
+        }
+    }
+
+    fn enter_map(&mut self, id: NetId, map_name: &str, tile_pos: IVec3) {
+        let player = self.lobby[&id];
+        let addr = self.server.client_addr(id).unwrap();
+        let blocking = self.world.get::<Collider>(player).is_some();
+
+        self.world.get_mut::<MapId>(player).unwrap().name = map_name.to_string();
+        self.world.get_mut::<TilePos>(player).unwrap().vec = tile_pos;
+        self.world.get_mut::<TargetTilePos>(player).unwrap().vec = tile_pos;
+        self.world
+            .get_mut::<LoadedChunks>(player)
+            .unwrap()
+            .chunks
+            .clear();
+        self.world
+            .get_mut::<VisiblePlayers>(player)
+            .unwrap()
+            .ids
+            .clear();
+
+        let map = self.maps.get_mut(map_name).unwrap();
+        map.collision_grid.occupy(player, tile_pos, blocking);
+
+        let map = self.maps.get(map_name).unwrap();
+        let position = map.tiled.tile_to_world(tile_pos.truncate());
+        let floor = tile_pos.z;
+
+        let msg = ServerMessage::MapChanged {
+            map: map_name.to_string(),
+            hash: map.hash,
+            position,
+            floor,
+        };
+        self.server
+            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+        let msg = ServerMessage::WorldTime { time: self.time };
+        self.server
+            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+        self.stream_chunks(id);
+
+        println!(
+            "Player {} entered {} at tile: {:?}, world: {:?}",
+            id, map_name, tile_pos, position
+        );
+
+        let msg = self.spawn_message(id, position);
+        self.server
+            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+        self.update_visibility(map_name);
+        self.send_effect_on(id, EffectKind::Poof);
+    }
+
+    // clients only ever need the map they're on, and only so often
+    fn request_map(&mut self, id: NetId, map_name: String) {
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        if self.player_map(player) != map_name {
+            eprintln!("Client {} asked for {}, which they aren't on", id, map_name);
+            return;
+        }
+        if self.download_cooldowns.contains_key(&id) || self.uploads.contains_key(&id) {
+            self.queued_downloads.insert(id, map_name);
+            return;
+        }
+
+        self.send_map(id, &map_name);
+    }
+
+    // a download asked for too soon goes out once the cooldown is over
+    fn update_downloads(&mut self, dt: f32) {
+        let mut ready = Vec::new();
+        self.download_cooldowns.retain(|&id, time| {
+            *time -= dt;
+            if *time > 0.0 {
+                return true;
+            }
+            ready.push(id);
+            false
+        });
+
+        for id in ready {
+            if let Some(map_name) = self.queued_downloads.remove(&id) {
+                self.request_map(id, map_name);
+            }
+        }
+    }
+
+    // starts streaming our copy of a map to a client whose own copy is missing
+    // or differs; the parts follow a few at a time from `update_uploads`
+    fn send_map(&mut self, id: NetId, map_name: &str) {
+        let (Some(map), Some(addr)) = (self.maps.get(map_name), self.server.client_addr(id)) else {
+            return;
+        };
+        println!("Sending map {} to client {}", map_name, id);
+
+        let msg = ServerMessage::MapDownload {
+            map: map_name.to_string(),
+            hash: map.hash,
+            files: map
+                .bundle
+                .files
+                .iter()
+                .map(|f| (f.path.clone(), f.bytes.len() as u32))
+                .collect(),
+        };
+        self.server
+            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+
+        let upload = Upload {
+            map: map_name.to_string(),
+            file: 0,
+            offset: 0,
+        };
+        self.uploads.insert(id, upload);
+    }
+
+    // sends the next parts of each download, and starts the client's cooldown
+    // once it's all out
+    fn update_uploads(&mut self) {
+        let mut finished = Vec::new();
+
+        for (&id, upload) in &mut self.uploads {
+            let (Some(map), Some(addr)) = (self.maps.get(&upload.map), self.server.client_addr(id))
+            else {
+                finished.push(id);
+                continue;
+            };
+
+            let mut parts = 0;
+            while parts < MAP_PARTS_PER_TICK {
+                let Some(file) = map.bundle.files.get(upload.file) else {
+                    finished.push(id);
+                    break;
+                };
+
+                let end = (upload.offset + MAP_PART_SIZE).min(file.bytes.len());
+                if end > upload.offset {
+                    let msg = ServerMessage::MapFilePart {
+                        map: upload.map.clone(),
+                        path: file.path.clone(),
+                        offset: upload.offset as u32,
+                        bytes: file.bytes[upload.offset..end].to_vec(),
                     };
                     self.server
-                        .broadcast_reliable(&serialize(&msg).unwrap(), true);
+                        .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+                    parts += 1;
                 }
-                ServerEvent::ClientDisconnected(id) => {
-                    println!("Client {} disconnected.", id);
 
-                    self.lobby.remove(&id).map(|p| self.world.despawn(p));
-                    
-                    let msg = ServerMessage::PlayerDespawned { id };
-                    self.server.broadcast_reliable(&serialize(&msg).unwrap(), true);
+                upload.offset = end;
+                if upload.offset == file.bytes.len() {
+                    upload.file += 1;
+                    upload.offset = 0;
+                }
+            }
+        }
+
+        for id in finished {
+            self.uploads.remove(&id);
+            self.download_cooldowns.insert(id, MAP_DOWNLOAD_COOLDOWN);
+        }
+    }
+
+    // walking by hand stops following whoever the player was following
+    fn move_player(&mut self, id: NetId, input: ClientInput) {
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        self.world.get_mut::<Actions>(player).unwrap().following = None;
+        self.step_player(id, input.to_direction());
+    }
+
+    // returns whether the player could take the step
+    fn step_player(&mut self, id: NetId, direction: IVec2) -> bool {
+        let Some(&player) = self.lobby.get(&id) else {
+            return false;
+        };
+        let map_name = self.player_map(player);
+        let map = self.maps.get_mut(&map_name).unwrap();
+
+        if self.world.get::<Actions>(player).unwrap().step_cooldown > STEP_SLACK {
+            return false;
+        }
+
+        let target_pos = self.world.get::<TargetTilePos>(player).unwrap().vec;
+        let mut next_pos = target_pos + direction.extend(0);
+        if next_pos == target_pos || !map.is_walkable(next_pos) {
+            return false;
+        }
+
+        // stairs, ladders and holes take the player straight to another floor
+        if let Some(floor_pos) = map.floor_change_target(next_pos) {
+            next_pos = floor_pos;
+        }
+
+        map.collision_grid
+            .move_occupant(player, target_pos, next_pos);
+        let kinds = self.world.get::<Conditions>(player).unwrap().kinds();
+        self.world.get_mut::<Actions>(player).unwrap().step_cooldown = step_interval(&kinds);
+        self.world.get_mut::<TargetTilePos>(player).unwrap().vec = next_pos;
+        self.world.get_mut::<TilePos>(player).unwrap().vec = next_pos;
+
+        // a portal to a missing spawn is walked over like any other tile
+        let map = self.maps.get(&map_name).unwrap();
+        let destination = map.get_portal(next_pos).and_then(|portal| {
+            let spawn_pos = self
+                .maps
+                .get(&portal.map)
+                .and_then(|m| m.get_spawn(&portal.spawn));
+            if spawn_pos.is_none() {
+                eprintln!(
+                    "Portal on {} leads to missing spawn {} on {}",
+                    map_name, portal.spawn, portal.map
+                );
+            }
+            Some((portal, spawn_pos?))
+        });
+        if let Some((portal, spawn_pos)) = destination {
+            self.hide_player(id);
+            self.leave_map(player);
+            self.enter_map(id, &portal.map, spawn_pos);
+            return true;
+        }
+
+        self.stream_chunks(id);
+        self.update_visibility(&map_name);
+
+        let map = self.maps.get(&map_name).unwrap();
+        let msg = ServerMessage::PlayerMoved {
+            id,
+            position: map.tiled.tile_to_world(next_pos.truncate()),
+            floor: next_pos.z,
+        };
+        self.send_to_viewers(id, &msg, false);
+
+        if let Some(field) = map.tiled.field_at(next_pos) {
+            let condition = Condition::new(field.condition, field.duration, field.power, None);
+            self.add_condition(id, condition);
+        }
+        true
+    }
+
+    fn add_condition(&mut self, id: NetId, condition: Condition) {
+        let player = self.lobby[&id];
+        let kind = condition.kind;
+        let (changed, appeared) = {
+            let mut conditions = self.world.get_mut::<Conditions>(player).unwrap();
+            let had = conditions.has(kind);
+            (conditions.add(condition), !had)
+        };
+        if changed {
+            self.conditions_changed(id, appeared && kind == ConditionKind::Invisibility);
+        }
+    }
+
+    // only invisibility coming or going changes who sees the player, anything
+    // else just goes to the players already watching
+    fn conditions_changed(&self, id: NetId, visibility_changed: bool) {
+        let player = self.lobby[&id];
+        let conditions = self.world.get::<Conditions>(player).unwrap().kinds();
+        let msg = ServerMessage::ConditionsChanged { id, conditions };
+        self.send_to_viewers(id, &msg, true);
+        if visibility_changed {
+            self.update_visibility(&self.player_map(player));
+        }
+    }
+
+    // conditions run out, and the ones that hurt or heal do so on every tick
+    fn update_conditions(&mut self, dt: f32) {
+        let ids: Vec<NetId> = self.lobby.keys().copied().collect();
+
+        for id in ids {
+            let Some(&player) = self.lobby.get(&id) else {
+                continue;
+            };
+            let (ticks, expired) = self.world.get_mut::<Conditions>(player).unwrap().update(dt);
+            if !expired.is_empty() {
+                let revealed = expired.contains(&ConditionKind::Invisibility);
+                self.conditions_changed(id, revealed);
+            }
+
+            for (kind, power, source) in ticks {
+                let effect = match kind {
+                    ConditionKind::Burning => EffectKind::Fire,
+                    ConditionKind::Electrified => EffectKind::Energy,
+                    ConditionKind::Regeneration => EffectKind::Heal,
+                    _ => EffectKind::Poison,
+                };
+                self.send_effect_on(id, effect);
+
+                let died = match kind {
+                    ConditionKind::Regeneration => {
+                        self.heal(id, power, source);
+                        false
+                    }
+                    _ => self.damage(id, power, source),
+                };
+                // dying clears the rest
+                if died {
+                    break;
                 }
-                ServerEvent::MessageReceived(id, bytes) => {
-                    let ClientMessage::PlayerUpdate { input } = deserialize(&bytes).unwrap();
+            }
+        }
+    }
+
+    // actions are only allowed on players the player can see, which also
+    // keeps them to the same map
+    fn interact(&mut self, id: NetId, target: NetId, action: EntityAction) {
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        let visible = self
+            .world
+            .get::<VisiblePlayers>(player)
+            .unwrap()
+            .ids
+            .contains(&target);
+        let Some(&other) = self.lobby.get(&target).filter(|_| visible) else {
+            self.send_text(id, "You can't see them from here.".to_string());
+            return;
+        };
+        let name = self.world.get::<Name>(player).unwrap().name.clone();
+        let other_name = self.world.get::<Name>(other).unwrap().name.clone();
+
+        match action {
+            EntityAction::Look => {
+                let health = self.world.get::<Health>(other).unwrap().percent();
+                self.send_text(id, format!("You see {} ({}% health).", other_name, health));
+            }
+            EntityAction::Attack => {
+                self.world.get_mut::<Actions>(player).unwrap().attacking = Some(target);
+            }
+            EntityAction::Follow => {
+                self.world.get_mut::<Actions>(player).unwrap().following = Some(target);
+            }
+            // there's no trading yet, so the other player is only asked
+            EntityAction::Trade => {
+                self.send_text(target, format!("{} wants to trade with you.", name));
+                self.send_text(id, format!("You asked {} to trade.", other_name));
+            }
+            EntityAction::InviteToParty => self.invite_to_party(id, target),
+        }
+    }
+
+    fn same_party(&self, id: NetId, other: NetId) -> bool {
+        let party = self.parties.get(&id);
+        party.is_some() && party == self.parties.get(&other)
+    }
+
+    fn party_members(&self, leader: NetId) -> Vec<NetId> {
+        self.parties
+            .iter()
+            .filter(|&(_, &l)| l == leader)
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    fn send_party(&self, leader: NetId) {
+        let members = self.party_members(leader);
+        let msg = ServerMessage::PartyChanged {
+            members: members.clone(),
+        };
+        for id in members {
+            self.send_reliable(id, &msg);
+        }
+    }
+
+    // an invite is accepted by inviting back, which joins the inviter's party
+    fn invite_to_party(&mut self, id: NetId, target: NetId) {
+        let name = self
+            .world
+            .get::<Name>(self.lobby[&id])
+            .unwrap()
+            .name
+            .clone();
+        let other_name = self
+            .world
+            .get::<Name>(self.lobby[&target])
+            .unwrap()
+            .name
+            .clone();
+
+        if self.same_party(id, target) {
+            self.send_text(id, format!("{} is already in your party.", other_name));
+            return;
+        }
+
+        if !self.party_invites.remove(&(target, id)) {
+            self.party_invites.insert((id, target));
+            self.send_text(target, format!("{} invited you to their party.", name));
+            self.send_text(id, format!("You invited {} to your party.", other_name));
+            return;
+        }
+
+        self.leave_party(id);
+        let leader = *self.parties.entry(target).or_insert(target);
+        self.parties.insert(id, leader);
+        for member in self.party_members(leader) {
+            self.send_text(member, format!("{} joined the party.", name));
+        }
+        self.send_party(leader);
+    }
+
+    // a party of one is no party
+    fn leave_party(&mut self, id: NetId) {
+        self.party_invites
+            .retain(|&(from, to)| from != id && to != id);
+        let Some(leader) = self.parties.remove(&id) else {
+            return;
+        };
+        self.send_reliable(
+            id,
+            &ServerMessage::PartyChanged {
+                members: Vec::new(),
+            },
+        );
+
+        let members = self.party_members(leader);
+        if let [last] = members[..] {
+            self.parties.remove(&last);
+            self.send_reliable(
+                last,
+                &ServerMessage::PartyChanged {
+                    members: Vec::new(),
+                },
+            );
+        } else {
+            self.send_party(leader);
+        }
+    }
+
+    fn cancel_actions(&mut self, id: NetId) {
+        if let Some(&player) = self.lobby.get(&id) {
+            let mut actions = self.world.get_mut::<Actions>(player).unwrap();
+            actions.attacking = None;
+            actions.following = None;
+        }
+    }
 
-                    if let Some(&player) = self.lobby.get(&id) {
-                        let mut target_pos = self.world.get_mut::<TargetTilePos>(player).unwrap();
-                        let mut tile_pos = self.world.get_mut::<TilePos>(player).unwrap();
+    // steps toward the target, around whatever is straight in the way
+    fn follow(&mut self, id: NetId, target: NetId) -> bool {
+        let tile_pos = self.world.get::<TilePos>(self.lobby[&id]).unwrap().vec;
+        let target_pos = self.world.get::<TilePos>(self.lobby[&target]).unwrap().vec;
+        let delta = (target_pos - tile_pos).truncate();
+        if delta.abs().max_element() <= 1 {
+            return false;
+        }
 
-                        let next_pos = target_pos.vec + input.to_direction();
+        let direction = delta.signum();
+        [direction, direction.with_y(0), direction.with_x(0)]
+            .into_iter()
+            .filter(|d| *d != IVec2::ZERO)
+            .any(|d| self.step_player(id, d))
+    }
 
-                        if self.map.is_walkable(next_pos, &self.collision_grid) {
-                            target_pos.vec = next_pos;
-                            tile_pos.vec = next_pos;
+    // melee reaches the 8 tiles around the attacker
+    fn attack(&mut self, id: NetId, target: NetId) -> bool {
+        let (player, other) = (self.lobby[&id], self.lobby[&target]);
+        if self.player_map(player) != self.player_map(other) {
+            return false;
+        }
+
+        let tile_pos = self.world.get::<TilePos>(player).unwrap().vec;
+        let target_pos = self.world.get::<TilePos>(other).unwrap().vec;
+        if tile_pos.z != target_pos.z || (target_pos - tile_pos).abs().max_element() > 1 {
+            return false;
+        }
 
-                            let msg = ServerMessage::PlayerMoved {
-                                id,
-                                position: self.map.tiled.tile_to_world(tile_pos.vec),
-                            };
-                            self.server.broadcast(&serialize(&msg).unwrap());
-                        }
+        self.send_effect_on(target, EffectKind::Blood);
+        self.damage(target, ATTACK_DAMAGE, Some(id));
+        true
+    }
+
+    // returns whether it killed them
+    fn damage(&mut self, id: NetId, amount: u32, source: Option<NetId>) -> bool {
+        let player = self.lobby[&id];
+        let (health, lost, dead) = {
+            let mut health = self.world.get_mut::<Health>(player).unwrap();
+            let lost = amount.min(health.current);
+            health.current -= lost;
+            (health.percent(), lost, health.current == 0)
+        };
+
+        // the killing blow is still seen before the player is moved away
+        let msg = ServerMessage::HealthChanged {
+            id,
+            health,
+            change: -(lost as i32),
+            source,
+        };
+        self.send_to_viewers(id, &msg, true);
+
+        if dead {
+            self.kill(id);
+            if let Some(killer) = source.filter(|&k| k != id && self.lobby.contains_key(&k)) {
+                self.level_up(killer);
+            }
+        }
+        dead
+    }
+
+    // every kill is worth a level, which is what unlocks the stronger spells
+    fn level_up(&mut self, id: NetId) {
+        let player = self.lobby[&id];
+        let level = {
+            let mut magic = self.world.get_mut::<Magic>(player).unwrap();
+            magic.level += 1;
+            magic.level
+        };
+        self.send_text(id, format!("You advanced to level {}.", level));
+    }
+
+    fn heal(&mut self, id: NetId, amount: u32, source: Option<NetId>) {
+        let player = self.lobby[&id];
+        let (health, gained) = {
+            let mut health = self.world.get_mut::<Health>(player).unwrap();
+            let gained = amount.min(health.max - health.current);
+            health.current += gained;
+            (health.percent(), gained)
+        };
+        if gained == 0 {
+            return;
+        }
+
+        let msg = ServerMessage::HealthChanged {
+            id,
+            health,
+            change: gained as i32,
+            source,
+        };
+        self.send_to_viewers(id, &msg, true);
+    }
+
+    // dead players wake up at the start with their health back
+    fn kill(&mut self, id: NetId) {
+        let player = self.lobby[&id];
+        self.send_text(id, "You are dead.".to_string());
+
+        let mut health = self.world.get_mut::<Health>(player).unwrap();
+        health.current = health.max;
+        drop(health);
+        self.world
+            .get_mut::<Conditions>(player)
+            .unwrap()
+            .active
+            .clear();
+        self.cancel_actions(id);
+
+        let spawn_pos = self
+            .maps
+            .get(DEFAULT_MAP)
+            .unwrap()
+            .get_spawn("player")
+            .unwrap();
+        self.hide_player(id);
+        self.leave_map(player);
+        self.enter_map(id, DEFAULT_MAP, spawn_pos);
+    }
+
+    // attacks and follows run on their own timers, and stop once the target
+    // is out of sight
+    fn update_actions(&mut self, dt: f32) {
+        let ids: Vec<NetId> = self.lobby.keys().copied().collect();
+
+        for id in ids {
+            let Some(&player) = self.lobby.get(&id) else {
+                continue;
+            };
+            let (attack, follow) = {
+                let visible = self.world.get::<VisiblePlayers>(player).unwrap();
+                let mut actions = self.world.get_mut::<Actions>(player).unwrap();
+                actions.attacking = actions.attacking.filter(|t| visible.ids.contains(t));
+                actions.following = actions.following.filter(|t| visible.ids.contains(t));
+                actions.attack_cooldown = (actions.attack_cooldown - dt).max(0.0);
+                actions.follow_cooldown = (actions.follow_cooldown - dt).max(0.0);
+                actions.step_cooldown = (actions.step_cooldown - dt).max(0.0);
+
+                (
+                    actions.attacking.filter(|_| actions.attack_cooldown == 0.0),
+                    actions.following.filter(|_| actions.follow_cooldown == 0.0),
+                )
+            };
+
+            if let Some(target) = follow
+                && self.follow(id, target)
+            {
+                self.world
+                    .get_mut::<Actions>(player)
+                    .unwrap()
+                    .follow_cooldown = FOLLOW_INTERVAL;
+            }
+            // following can take the player off the map, attacks can kill the target
+            if let Some(target) = attack
+                && self.lobby.contains_key(&target)
+                && self.attack(id, target)
+            {
+                self.world
+                    .get_mut::<Actions>(player)
+                    .unwrap()
+                    .attack_cooldown = ATTACK_INTERVAL;
+            }
+        }
+    }
+
+    fn send_runes(&self, id: NetId) {
+        let player = self.lobby[&id];
+        let runes = self
+            .world
+            .get::<Magic>(player)
+            .unwrap()
+            .runes
+            .iter()
+            .map(|(rune, &charges)| (rune.clone(), charges))
+            .collect();
+        self.send_reliable(id, &ServerMessage::Runes { runes });
+    }
+
+    // everyone nearby hears it, and incantations are cast
+    fn say(&mut self, id: NetId, text: &str) {
+        let text = text.trim();
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        if text.is_empty() || text.len() > MAX_SAY_LENGTH {
+            return;
+        }
+
+        let name = self.world.get::<Name>(player).unwrap().name.clone();
+        let msg = ServerMessage::TextMessage {
+            text: format!("{}: {}", name, text),
+        };
+        self.send_to_viewers(id, &msg, true);
+
+        if let Some(spell) = self.spells.by_words(text).cloned() {
+            self.cast(id, &spell, false);
+        }
+    }
+
+    fn use_rune(&mut self, id: NetId, rune: &str) {
+        let Some(&player) = self.lobby.get(&id) else {
+            return;
+        };
+        let charges = self
+            .world
+            .get::<Magic>(player)
+            .unwrap()
+            .runes
+            .get(rune)
+            .copied();
+        let Some(spell) = charges.and(self.spells.get(rune).cloned()) else {
+            self.send_text(id, format!("You have no {}.", rune));
+            return;
+        };
+
+        if self.cast(id, &spell, true) {
+            self.world.get_mut::<Magic>(player).unwrap().use_rune(rune);
+            self.send_runes(id);
+        }
+    }
+
+    // players standing on `tiles`
+    fn players_on(&self, map_name: &str, tiles: &[IVec2], floor: i32) -> Vec<NetId> {
+        let map = self.maps.get(map_name).unwrap();
+        let entities: Vec<Entity> = tiles
+            .iter()
+            .flat_map(|tile| map.collision_grid.occupants(tile.extend(floor)))
+            .collect();
+        self.lobby
+            .iter()
+            .filter(|(_, e)| entities.contains(e))
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    // aimed spells go at the player's attack target; runes cost a charge
+    // instead of mana
+    fn cast(&mut self, id: NetId, spell: &Spell, from_rune: bool) -> bool {
+        let player = self.lobby[&id];
+        let (level, exhausted) = {
+            let magic = self.world.get::<Magic>(player).unwrap();
+            (magic.level, magic.is_exhausted(spell.group))
+        };
+        let mana_cost = if from_rune { 0 } else { spell.mana };
+
+        if level < spell.level {
+            self.send_text(
+                id,
+                format!("You need level {} for {}.", spell.level, spell.name),
+            );
+            return false;
+        }
+        if self.world.get::<Mana>(player).unwrap().current < mana_cost {
+            self.send_text(id, "You don't have enough mana.".to_string());
+            return false;
+        }
+        if exhausted {
+            self.send_text(id, "You are exhausted.".to_string());
+            return false;
+        }
+
+        let map_name = self.player_map(player);
+        let caster = self.world.get::<TilePos>(player).unwrap().vec;
+        let target = self
+            .world
+            .get::<Actions>(player)
+            .unwrap()
+            .attacking
+            .and_then(|t| self.lobby.get(&t))
+            .map(|&other| self.world.get::<TilePos>(other).unwrap().vec)
+            .filter(|target| target.z == caster.z);
+        let Some(mut tiles) = spell
+            .area
+            .tiles(caster.truncate(), target.map(|t| t.truncate()))
+        else {
+            self.send_text(id, "You need a target.".to_string());
+            return false;
+        };
+
+        let map = self.maps.get(&map_name).unwrap();
+        if let Some(target) = target.filter(|_| spell.area.needs_target())
+            && !map.can_hit(caster, target)
+        {
+            self.send_text(id, "You can't reach them from here.".to_string());
+            return false;
+        }
+        // explosions spread from where they land, everything else from the caster
+        let origin = match (spell.area, target) {
+            (Area::TargetCircle { .. }, Some(target)) => target,
+            _ => caster,
+        };
+        tiles.retain(|tile| map.can_hit(origin, tile.extend(caster.z)));
+
+        let mana = {
+            let mut mana = self.world.get_mut::<Mana>(player).unwrap();
+            mana.current -= mana_cost;
+            mana.percent()
+        };
+        self.world
+            .get_mut::<Magic>(player)
+            .unwrap()
+            .start_cooldown(spell.group, spell.cooldown);
+        if mana_cost > 0 {
+            self.send_to_viewers(id, &ServerMessage::ManaChanged { id, mana }, true);
+        }
+
+        // thrown spells fly to the target before they go off
+        let mut delay = 0.0;
+        if let Some(target) = target.filter(|_| spell.area.is_thrown()) {
+            delay = (target - caster).truncate().as_vec2().length() / PROJECTILE_SPEED;
+            let msg = ServerMessage::Projectile {
+                effect: spell.animation(),
+                origin: caster.truncate(),
+                target: target.truncate(),
+                floor: caster.z,
+                duration: delay,
+            };
+            self.send_to_viewers(id, &msg, false);
+        }
+        self.send_effect(id, spell.animation(), tiles.clone(), caster.z, delay);
+
+        match &spell.effect {
+            // party members are spared
+            SpellEffect::Damage { power } => {
+                for other in self.players_on(&map_name, &tiles, caster.z) {
+                    if other != id && !self.same_party(id, other) && self.lobby.contains_key(&other)
+                    {
+                        self.damage(other, *power, Some(id));
+                    }
+                }
+            }
+            SpellEffect::Heal { power } => {
+                for other in self.players_on(&map_name, &tiles, caster.z) {
+                    self.heal(other, *power, Some(id));
+                }
+            }
+            SpellEffect::Condition {
+                condition,
+                duration,
+                power,
+            } => {
+                for other in self.players_on(&map_name, &tiles, caster.z) {
+                    let friendly = other == id || self.same_party(id, other);
+                    if condition.is_harmful() != friendly {
+                        let condition = Condition::new(*condition, *duration, *power, Some(id));
+                        self.add_condition(other, condition);
                     }
                 }
             }
+            SpellEffect::Conjure { rune, charges } => {
+                self.world
+                    .get_mut::<Magic>(player)
+                    .unwrap()
+                    .add_runes(rune, *charges);
+                self.send_runes(id);
+                self.send_text(id, format!("You conjured {} {}.", charges, rune));
+            }
         }
+        true
     }
 
-    pub fn update(&mut self, _dt: f32) {
+    // cooldowns wear off and mana comes back
+    fn update_magic(&mut self, dt: f32) {
+        self.mana_regen_timer += dt;
+        let regen = self.mana_regen_timer >= MANA_REGEN_INTERVAL;
+        if regen {
+            self.mana_regen_timer = 0.0;
+        }
+
+        for (&id, &player) in &self.lobby {
+            self.world
+                .get_mut::<Magic>(player)
+                .unwrap()
+                .update_cooldowns(dt);
+            if !regen {
+                continue;
+            }
+
+            let mana = {
+                let mut mana = self.world.get_mut::<Mana>(player).unwrap();
+                let before = mana.percent();
+                mana.current = (mana.current + MANA_REGEN).min(mana.max);
+                (mana.percent() != before).then(|| mana.percent())
+            };
+            if let Some(mana) = mana {
+                self.send_to_viewers(id, &ServerMessage::ManaChanged { id, mana }, true);
+            }
+        }
+    }
+
+    pub fn handle_events(&mut self) {
+        while let Some(event) = self.server.recv_event() {
+            match event {
+                ServerEvent::ClientConnected(id) => {
+                    let addr = self.server.client_addr(id).unwrap();
+                    println!("Client {} connected from {}", id, addr);
+
+                    if self.shutdown_timer.is_some() {
+                        self.refuse(id, "Server is shutting down");
+                    }
+                }
+                ServerEvent::ClientDisconnected(id) => {
+                    println!("Client {} disconnected.", id);
+
+                    self.save_player(id);
+                    self.leave_party(id);
+                    self.download_cooldowns.remove(&id);
+                    self.queued_downloads.remove(&id);
+                    self.uploads.remove(&id);
+                    if let Some(p) = self.lobby.remove(&id) {
+                        self.hide_player(id);
+                        self.leave_map(p);
+                        self.world.despawn(p);
+                    }
+                }
+                // whatever a client sends can be garbage, which is dropped
+                // rather than trusted
+                ServerEvent::MessageReceived(id, bytes) => match deserialize(&bytes) {
+                    Ok(ClientMessage::Login { name }) => self.login(id, name),
+                    Ok(ClientMessage::PlayerUpdate { input }) => self.move_player(id, input),
+                    Ok(ClientMessage::RequestMap { map }) => self.request_map(id, map),
+                    Ok(ClientMessage::Interact { target, action }) => {
+                        self.interact(id, target, action)
+                    }
+                    Ok(ClientMessage::CancelActions) => self.cancel_actions(id),
+                    Ok(ClientMessage::Say { text }) => self.say(id, &text),
+                    Ok(ClientMessage::UseRune { rune }) => self.use_rune(id, &rune),
+                    Err(e) => eprintln!("Dropped a malformed message from client {}: {}", id, e),
+                },
+            }
+        }
+    }
+
+    // players only join the world once they've said who they are, and each
+    // character can only be played once at a time
+    fn login(&mut self, id: NetId, name: String) {
+        if self.lobby.contains_key(&id) {
+            return;
+        }
+        let online = self.lobby.values().any(|&p| {
+            self.world
+                .get::<Name>(p)
+                .unwrap()
+                .name
+                .eq_ignore_ascii_case(&name)
+        });
+        let refusal = if !is_valid_name(&name) {
+            Some("Invalid character name")
+        } else if online {
+            Some("That character is already online")
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            self.refuse(id, reason);
+            return;
+        }
+        println!("Client {} logged in as {}", id, name);
+
+        // a save from an older map, or one edited by hand, can put them
+        // somewhere they can't stand, so they start over at the spawn
+        let character = self.storage.load(&name);
+        let (map_name, spawn_pos) = character
+            .as_ref()
+            .map(|c| (c.map.clone(), c.tile_pos.extend(c.floor)))
+            .filter(|(map_name, tile_pos)| {
+                self.maps
+                    .get(map_name)
+                    .is_some_and(|map| map.is_walkable(*tile_pos))
+            })
+            .unwrap_or_else(|| {
+                let map = self.maps.get(DEFAULT_MAP).unwrap();
+                (DEFAULT_MAP.to_string(), map.get_spawn("player").unwrap())
+            });
+        let player = self.world.spawn((
+            Player,
+            MapId {
+                name: map_name.clone(),
+            },
+            TilePos { vec: spawn_pos },
+            TargetTilePos { vec: spawn_pos },
+            Collider,
+            LoadedChunks::default(),
+            VisiblePlayers::default(),
+            Name { name },
+            Health::new(PLAYER_HEALTH),
+            Mana::new(PLAYER_MANA),
+            Magic {
+                level: character.as_ref().map_or(1, |c| c.level),
+                cooldowns: HashMap::new(),
+                runes: character.map(|c| c.runes).unwrap_or_default(),
+            },
+            Actions::default(),
+            Conditions::default(),
+        ));
+        self.lobby.insert(id, player);
+
+        self.enter_map(id, &map_name, spawn_pos);
+        self.send_runes(id);
+    }
+
+    // tells a client why before dropping its connection
+    fn refuse(&mut self, id: NetId, reason: &str) {
+        let Some(addr) = self.server.client_addr(id) else {
+            return;
+        };
+        println!("Refused client {}: {}", id, reason);
+
+        let msg = ServerMessage::Disconnected {
+            reason: reason.to_string(),
+        };
+        self.server
+            .send_reliable_to(&addr, &serialize(&msg).unwrap(), true);
+        self.server.disconnect(&addr);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if let Some(timer) = &mut self.shutdown_timer {
+            *timer -= dt;
+        }
+
+        // clients run the clock themselves in between, this only corrects drift
+        self.time = (self.time + dt).rem_euclid(DAY_LENGTH);
+        self.clock_sync_timer += dt;
+        if self.clock_sync_timer >= CLOCK_SYNC {
+            self.clock_sync_timer = 0.0;
+
+            let msg = ServerMessage::WorldTime { time: self.time };
+            self.server.broadcast(&serialize(&msg).unwrap());
+        }
+
+        self.update_actions(dt);
+        self.update_magic(dt);
+        self.update_downloads(dt);
+        self.update_uploads();
+        self.update_conditions(dt);
+
         self.server.poll();
-        self.collision_grid.update(&self.map, &self.world);
     }
 }
@@ -1,8 +1,10 @@
-mod components;
 mod game;
-mod map;
 
 use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
     thread,
     time::{Duration, Instant},
 };
@@ -10,15 +12,31 @@ use std::{
 use crate::game::Game;
 
 fn main() {
-    let mut game = Game::new();
+    let mut game = match Game::new() {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
     let frame_time = Duration::from_millis(33); // ~30 FPS
+    let shutdown_grace = 5.0; // seconds players get to finish up
     let mut last = Instant::now();
 
-    loop {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+        .expect("Failed to set signal handler");
+
+    while game.is_running() {
         let now = Instant::now();
         let dt = (now - last).as_secs_f32();
         last = now;
 
+        if interrupted.load(Ordering::SeqCst) {
+            game.begin_shutdown(shutdown_grace);
+        }
+
         game.handle_events();
         game.update(dt);
 
@@ -27,4 +45,6 @@ fn main() {
             thread::sleep(frame_time - elapsed);
         }
     }
+
+    game.shutdown();
 }
@@ -1,76 +1,455 @@
-use dyrah_shared::map::TiledMap;
-use secs::World;
+use std::{collections::HashMap, path::Path};
 
-use crate::components::{Collider, TilePos};
-use glam::{IVec2, Vec2};
+use dyrah_shared::{
+    los,
+    map::{CHUNK_SIZE, MapBundle, MapError, TiledMap, chunk_of, chunk_positions, list_maps},
+};
+use secs::Entity;
+
+use glam::IVec3;
+
+struct ChunkGrid {
+    walk: Vec<bool>,
+    projectiles: Vec<bool>,
+    sight: Vec<bool>,
+}
+
+#[derive(Clone, Copy)]
+struct Occupant {
+    entity: Entity,
+    blocking: bool,
+}
 
 pub struct CollisionGrid {
-    width: usize,
-    height: usize,
-    grid: Vec<bool>,
+    chunks: HashMap<IVec3, ChunkGrid>,
+    occupants: HashMap<IVec3, Vec<Occupant>>,
 }
 
 impl CollisionGrid {
-    pub fn new(map: &Map) -> Self {
-        let width = map.tiled.width as usize;
-        let height = map.tiled.height as usize;
+    // the map itself never changes, so its blocking is computed once up front
+    // and entities are tracked separately as they spawn, move and despawn;
+    // only chunks with tiles are stored so infinite maps stay sparse
+    pub fn new(tiled: &TiledMap) -> Self {
+        let size = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        let mut chunks = HashMap::new();
+
+        for z in tiled.floors() {
+            for chunk in tiled.chunks() {
+                let mut grid = ChunkGrid {
+                    walk: vec![false; size],
+                    projectiles: vec![false; size],
+                    sight: vec![false; size],
+                };
+
+                for (i, tile_pos) in chunk_positions(chunk).enumerate() {
+                    let blocking = tiled.blocking("colliders", tile_pos.extend(z));
+                    grid.walk[i] = blocking.walk;
+                    grid.projectiles[i] = blocking.projectiles;
+                    grid.sight[i] = blocking.sight;
+                }
+
+                chunks.insert(chunk.extend(z), grid);
+            }
+        }
+
         Self {
-            width,
-            height,
-            grid: vec![false; width * height],
+            chunks,
+            occupants: HashMap::new(),
         }
     }
 
-    pub fn update(&mut self, map: &Map, world: &World) {
-        self.grid.fill(false);
+    pub fn occupy(&mut self, entity: Entity, tile_pos: IVec3, blocking: bool) {
+        self.occupants
+            .entry(tile_pos)
+            .or_default()
+            .push(Occupant { entity, blocking });
+    }
+
+    pub fn vacate(&mut self, entity: Entity, tile_pos: IVec3) -> bool {
+        let Some(tile) = self.occupants.get_mut(&tile_pos) else {
+            return false;
+        };
+        let Some(i) = tile.iter().position(|o| o.entity == entity) else {
+            return false;
+        };
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let tile_pos = IVec2::new(x as i32, y as i32);
-                if !map.tiled.is_walkable("colliders", tile_pos) {
-                    self.grid[y * self.width + x] = true;
-                }
-            }
+        let occupant = tile.swap_remove(i);
+        if tile.is_empty() {
+            self.occupants.remove(&tile_pos);
         }
 
-        world.query(|_, _: &Collider, tile_pos: &TilePos| {
-            let x = tile_pos.vec.x as usize;
-            let y = tile_pos.vec.y as usize;
+        occupant.blocking
+    }
 
-            if x < self.width && y < self.height {
-                self.grid[y * self.width + x] = true;
-            }
-        });
+    pub fn move_occupant(&mut self, entity: Entity, from: IVec3, to: IVec3) {
+        let blocking = self.vacate(entity, from);
+        self.occupy(entity, to, blocking);
+    }
+
+    pub fn occupants(&self, tile_pos: IVec3) -> impl Iterator<Item = Entity> + '_ {
+        self.occupants
+            .get(&tile_pos)
+            .into_iter()
+            .flatten()
+            .map(|o| o.entity)
     }
 
-    pub fn is_walkable(&self, tile_pos: IVec2) -> bool {
-        let (x, y) = (tile_pos.x as usize, tile_pos.y as usize);
-        if x >= self.width || y >= self.height {
-            false
-        } else {
-            !self.grid[y * self.width + x]
+    pub fn is_occupied(&self, tile_pos: IVec3) -> bool {
+        self.occupants
+            .get(&tile_pos)
+            .is_some_and(|tile| tile.iter().any(|o| o.blocking))
+    }
+
+    fn is_blocked(&self, tile_pos: IVec3, layer: impl Fn(&ChunkGrid) -> &Vec<bool>) -> bool {
+        let (chunk, i) = chunk_of(tile_pos.truncate());
+        match self.chunks.get(&chunk.extend(tile_pos.z)) {
+            Some(grid) => layer(grid)[i],
+            None => true,
         }
     }
+
+    pub fn is_walkable(&self, tile_pos: IVec3) -> bool {
+        !self.is_blocked(tile_pos, |g| &g.walk) && !self.is_occupied(tile_pos)
+    }
+
+    pub fn blocks_projectiles(&self, tile_pos: IVec3) -> bool {
+        self.is_blocked(tile_pos, |g| &g.projectiles)
+    }
+
+    pub fn blocks_sight(&self, tile_pos: IVec3) -> bool {
+        self.is_blocked(tile_pos, |g| &g.sight)
+    }
+}
+
+pub struct Portal {
+    pub map: String,
+    pub spawn: String,
 }
 
 pub struct Map {
     pub tiled: TiledMap,
+    pub collision_grid: CollisionGrid,
+    // the server's copy is the one clients download when theirs differs
+    pub bundle: MapBundle,
+    pub hash: u64,
 }
 
 impl Map {
-    pub fn new(path: &str) -> Self {
-        Self {
-            tiled: TiledMap::new(path),
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        let tiled = TiledMap::load(path)?;
+        let bundle = MapBundle::collect(path, &tiled)?;
+        for missing in &bundle.missing {
+            eprintln!("Map {} is missing {}", path.display(), missing);
         }
+        Ok(Self {
+            collision_grid: CollisionGrid::new(&tiled),
+            hash: bundle.hash(),
+            bundle,
+            tiled,
+        })
     }
 
-    pub fn get_spawn(&self, name: &str) -> Option<IVec2> {
+    pub fn get_spawn(&self, name: &str) -> Option<IVec3> {
         self.tiled
             .get_object("spawns", name)
-            .map(|o| self.tiled.world_to_tile(Vec2::new(o.x, o.y)))
+            .map(|o| self.tiled.object_tiles(o).0.extend(o.floor))
+    }
+
+    pub fn get_portal(&self, tile_pos: IVec3) -> Option<Portal> {
+        let mut objects = self
+            .tiled
+            .get_layers("portals")
+            .filter(|l| l.floor == tile_pos.z)
+            .flat_map(|l| l.objects.iter().flatten());
+
+        objects.find_map(|o| {
+            if !self.tiled.object_contains(o, tile_pos.truncate()) {
+                return None;
+            }
+
+            Some(Portal {
+                map: o.properties.get_string("map")?.to_string(),
+                spawn: o
+                    .properties
+                    .get_string("spawn")
+                    .unwrap_or("player")
+                    .to_string(),
+            })
+        })
+    }
+
+    pub fn is_walkable(&self, tile_pos: IVec3) -> bool {
+        self.collision_grid.is_walkable(tile_pos)
+    }
+
+    // whether anything at `from` can see `to`, for what players are told
+    // about and what monsters notice
+    pub fn can_see(&self, from: IVec3, to: IVec3) -> bool {
+        from.z == to.z
+            && los::can_see(from.truncate(), to.truncate(), |pos| {
+                self.collision_grid.blocks_sight(pos.extend(from.z))
+            })
+    }
+
+    // whether a spell or projectile from `from` gets to `to`, which can't be
+    // inside a wall itself
+    pub fn can_hit(&self, from: IVec3, to: IVec3) -> bool {
+        from.z == to.z
+            && (from == to || !self.collision_grid.blocks_projectiles(to))
+            && los::has_line_of_sight(from.truncate(), to.truncate(), |pos| {
+                self.collision_grid.blocks_projectiles(pos.extend(from.z))
+            })
     }
 
-    pub fn is_walkable(&self, tile_pos: IVec2, grid: &CollisionGrid) -> bool {
-        grid.is_walkable(tile_pos)
+    // where a floor change from `tile_pos` lands, falling back to the nearest
+    // walkable neighbour when the tile straight above/below is blocked
+    pub fn floor_change_target(&self, tile_pos: IVec3) -> Option<IVec3> {
+        let change = self.tiled.floor_change(tile_pos)?;
+        let target = tile_pos + IVec3::Z * change;
+
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| IVec3::new(dx, dy, 0)))
+            .map(|offset| target + offset)
+            .filter(|&pos| self.is_walkable(pos))
+            .min_by_key(|&pos| (pos - target).abs().element_sum())
+    }
+}
+
+pub struct Maps {
+    maps: HashMap<String, Map>,
+}
+
+impl Maps {
+    // broken maps are reported and skipped rather than taking the server down
+    pub fn load(dir: &str) -> Self {
+        let mut maps = HashMap::new();
+
+        for (name, path) in list_maps(dir) {
+            println!("Loading map: {}", path.display());
+
+            match Map::load(&path) {
+                Ok(map) => {
+                    maps.insert(name, map);
+                }
+                Err(e) => eprintln!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+
+        let maps = Self { maps };
+        maps.check_portals();
+        maps
+    }
+
+    fn check_portals(&self) {
+        for (name, map) in &self.maps {
+            for layer in map.tiled.get_layers("portals") {
+                for object in layer.objects.iter().flatten() {
+                    let Some(target) = object.properties.get_string("map") else {
+                        eprintln!("Portal {} on {} has no map property", object.name, name);
+                        continue;
+                    };
+                    let spawn = object.properties.get_string("spawn").unwrap_or("player");
+
+                    match self.maps.get(target) {
+                        None => eprintln!(
+                            "Portal {} on {} leads to missing map {}",
+                            object.name, name, target
+                        ),
+                        Some(m) if m.get_spawn(spawn).is_none() => eprintln!(
+                            "Portal {} on {} leads to missing spawn {} on {}",
+                            object.name, name, spawn, target
+                        ),
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Map> {
+        self.maps.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Map> {
+        self.maps.get_mut(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secs::World;
+
+    use super::*;
+    use crate::components::{Collider, TilePos};
+
+    // a wall (#) blocks everything and a curtain (C) only blocks sight
+    const LAYOUT: [&str; 5] = ["..C..", ".....", "..#..", "...#.", "....."];
+
+    fn test_map() -> Map {
+        let data: Vec<u32> = LAYOUT
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                '#' => 2,
+                'C' => 3,
+                _ => 0,
+            })
+            .collect();
+        let json = format!(
+            r#"{{
+                "width": 5, "height": 5, "tilewidth": 32, "tileheight": 32,
+                "layers": [
+                    {{ "name": "floor", "visible": true, "width": 5, "height": 5, "data": {floor:?} }},
+                    {{ "name": "walls", "visible": true, "width": 5, "height": 5, "data": {data:?} }}
+                ],
+                "tilesets": [{{
+                    "firstgid": 1, "image": "test.png", "tilecount": 3, "tilewidth": 32, "tileheight": 32,
+                    "tiles": [
+                        {{ "id": 1, "properties": [
+                            {{ "name": "blocking", "type": "bool", "value": true }},
+                            {{ "name": "blocks_projectiles", "type": "bool", "value": true }},
+                            {{ "name": "blocks_sight", "type": "bool", "value": true }}
+                        ] }},
+                        {{ "id": 2, "properties": [
+                            {{ "name": "blocks_sight", "type": "bool", "value": true }}
+                        ] }}
+                    ]
+                }}]
+            }}"#,
+            floor = vec![1; 25],
+        );
+        let tiled = TiledMap::parse(&json).unwrap();
+
+        Map {
+            collision_grid: CollisionGrid::new(&tiled),
+            bundle: MapBundle {
+                files: Vec::new(),
+                missing: Vec::new(),
+            },
+            hash: 0,
+            tiled,
+        }
+    }
+
+    // the tile drawn at this column and row of the layout
+    fn at(x: i32, row: i32) -> IVec3 {
+        IVec3::new(x, row - 1, 0)
+    }
+
+    #[test]
+    fn same_tile_is_always_seen_and_hit() {
+        let map = test_map();
+        for pos in [at(0, 0), at(2, 2)] {
+            assert!(map.can_see(pos, pos));
+            assert!(map.can_hit(pos, pos));
+        }
+    }
+
+    #[test]
+    fn walls_are_seen_but_not_hit() {
+        let map = test_map();
+        assert!(map.can_see(at(0, 2), at(2, 2)));
+        assert!(!map.can_hit(at(0, 2), at(2, 2)));
+    }
+
+    #[test]
+    fn walls_block_what_is_behind_them() {
+        let map = test_map();
+        assert!(!map.can_see(at(0, 2), at(4, 2)));
+        assert!(!map.can_hit(at(0, 2), at(4, 2)));
+        assert!(map.can_see(at(0, 1), at(4, 1)));
+        assert!(map.can_hit(at(0, 1), at(4, 1)));
+    }
+
+    #[test]
+    fn diagonal_neighbours_see_past_corners() {
+        // nothing lies between diagonal neighbours, even with walls on both
+        // sides of the corner
+        let map = test_map();
+        assert!(map.can_see(at(2, 3), at(3, 2)));
+        assert!(map.can_hit(at(2, 3), at(3, 2)));
+        // a longer diagonal runs through the walls themselves
+        assert!(!map.can_see(at(1, 1), at(4, 4)));
+    }
+
+    #[test]
+    fn curtains_only_block_sight() {
+        let map = test_map();
+        assert!(!map.can_see(at(0, 0), at(4, 0)));
+        assert!(map.can_hit(at(0, 0), at(4, 0)));
+    }
+
+    #[test]
+    fn other_floors_are_never_seen() {
+        let map = test_map();
+        let above = at(1, 1) + IVec3::Z;
+        assert!(!map.can_see(at(1, 1), above));
+        assert!(!map.can_hit(at(1, 1), above));
+    }
+
+    fn entities<const N: usize>() -> [Entity; N] {
+        let mut world = World::default();
+        std::array::from_fn(|_| world.spawn((TilePos::default(), Collider)))
+    }
+
+    #[test]
+    fn tiles_stay_taken_until_everyone_leaves() {
+        let mut map = test_map();
+        let [a, b] = entities();
+        let tile = at(1, 1);
+
+        map.collision_grid.occupy(a, tile, true);
+        map.collision_grid.occupy(b, tile, true);
+        assert!(map.collision_grid.vacate(a, tile));
+        assert!(!map.is_walkable(tile));
+        assert_eq!(map.collision_grid.occupants(tile).collect::<Vec<_>>(), [b]);
+
+        assert!(map.collision_grid.vacate(b, tile));
+        assert!(map.is_walkable(tile));
+    }
+
+    #[test]
+    fn non_blocking_occupants_can_be_walked_over() {
+        let mut map = test_map();
+        let [ghost] = entities();
+        let tile = at(1, 1);
+
+        map.collision_grid.occupy(ghost, tile, false);
+        assert!(map.is_walkable(tile));
+        assert_eq!(map.collision_grid.occupants(tile).count(), 1);
+        assert!(!map.collision_grid.vacate(ghost, tile));
+        assert_eq!(map.collision_grid.occupants(tile).count(), 0);
+    }
+
+    #[test]
+    fn vacating_elsewhere_changes_nothing() {
+        let mut map = test_map();
+        let [a, b] = entities();
+
+        map.collision_grid.occupy(a, at(1, 1), true);
+        assert!(!map.collision_grid.vacate(b, at(1, 1)));
+        assert!(!map.collision_grid.vacate(a, at(4, 4)));
+        assert!(!map.is_walkable(at(1, 1)));
+        assert_eq!(
+            map.collision_grid.occupants(at(1, 1)).collect::<Vec<_>>(),
+            [a]
+        );
+    }
+
+    #[test]
+    fn moving_leaves_the_map_itself_alone() {
+        let mut map = test_map();
+        let [a] = entities();
+
+        // even stepping off a wall leaves the wall there
+        map.collision_grid.occupy(a, at(2, 2), true);
+        map.collision_grid.move_occupant(a, at(2, 2), at(1, 2));
+        assert!(!map.is_walkable(at(2, 2)));
+        assert!(!map.is_walkable(at(1, 2)));
+
+        map.collision_grid.move_occupant(a, at(1, 2), at(1, 1));
+        assert!(map.is_walkable(at(1, 2)));
+        assert!(!map.is_walkable(at(1, 1)));
+        assert!(!map.collision_grid.is_occupied(at(2, 2)));
     }
 }
@@ -0,0 +1,57 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, read_to_string},
+    io,
+    path::PathBuf,
+};
+
+use dyrah_shared::map::DEFAULT_MAP;
+use glam::IVec2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CharacterData {
+    #[serde(default = "default_map")]
+    pub map: String,
+    pub tile_pos: IVec2,
+    #[serde(default)]
+    pub floor: i32,
+    #[serde(default = "first_level")]
+    pub level: u32,
+    #[serde(default)]
+    pub runes: BTreeMap<String, u32>,
+}
+
+fn default_map() -> String {
+    DEFAULT_MAP.to_string()
+}
+
+fn first_level() -> u32 {
+    1
+}
+
+pub struct Storage {
+    dir: PathBuf,
+}
+
+impl Storage {
+    pub fn new(dir: &str) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // characters are saved by name, however it's capitalised
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name.to_lowercase()))
+    }
+
+    pub fn load(&self, name: &str) -> Option<CharacterData> {
+        let content = read_to_string(self.path(name)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, name: &str, data: &CharacterData) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let content = serde_json::to_string_pretty(data)?;
+        fs::write(self.path(name), content)
+    }
+}
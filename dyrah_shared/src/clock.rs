@@ -0,0 +1,19 @@
+// the time of day is kept by the server and followed by every client
+
+use std::f32::consts::TAU;
+
+// seconds in a game day, which starts at midnight
+pub const DAY_LENGTH: f32 = 20.0 * 60.0;
+
+// what's left of the light at midnight
+pub const NIGHT_LIGHT: f32 = 0.15;
+
+// from NIGHT_LIGHT through the night to 1 for most of the day, with dawn and
+// dusk in between
+pub fn ambient_light(time: f32) -> f32 {
+    let phase = (time / DAY_LENGTH).rem_euclid(1.0);
+    let sun = 0.5 - (phase * TAU).cos() * 0.5;
+    let day = (sun * 2.0 - 0.5).clamp(0.0, 1.0);
+
+    NIGHT_LIGHT + (1.0 - NIGHT_LIGHT) * day
+}
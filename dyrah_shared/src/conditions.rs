@@ -0,0 +1,93 @@
+// conditions are run by the server; clients only hear which ones each player
+// has, to show them and to walk at the right speed
+
+use serde::{Deserialize, Serialize};
+
+// seconds per step at normal speed
+pub const STEP_INTERVAL: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionKind {
+    Poison,
+    Burning,
+    Electrified,
+    Paralyze,
+    Haste,
+    Invisibility,
+    Regeneration,
+}
+
+impl ConditionKind {
+    pub const ALL: [Self; 7] = [
+        Self::Poison,
+        Self::Burning,
+        Self::Electrified,
+        Self::Paralyze,
+        Self::Haste,
+        Self::Invisibility,
+        Self::Regeneration,
+    ];
+
+    // as written in map properties and spell data
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Poison => "poison",
+            Self::Burning => "burning",
+            Self::Electrified => "electrified",
+            Self::Paralyze => "paralyze",
+            Self::Haste => "haste",
+            Self::Invisibility => "invisibility",
+            Self::Regeneration => "regeneration",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    // given to enemies rather than friends
+    pub fn is_harmful(&self) -> bool {
+        matches!(
+            self,
+            Self::Poison | Self::Burning | Self::Electrified | Self::Paralyze
+        )
+    }
+}
+
+// how long each step takes with `conditions`, the same on both ends so the
+// server doesn't turn away steps the client thinks are fine
+pub fn step_interval(conditions: &[ConditionKind]) -> f32 {
+    let mut interval = STEP_INTERVAL;
+    if conditions.contains(&ConditionKind::Paralyze) {
+        interval *= 2.0;
+    }
+    if conditions.contains(&ConditionKind::Haste) {
+        interval *= 0.6;
+    }
+    interval
+}
+
+// what a field gives unless its tile says otherwise
+const FIELD_POWER: u32 = 5;
+const FIELD_DURATION: f32 = 10.0;
+
+// a tile that gives whoever steps on it a condition, like a fire field
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub condition: ConditionKind,
+    // per tick, for the ones that hurt or heal
+    pub power: u32,
+    // in seconds
+    pub duration: f32,
+}
+
+impl Field {
+    pub fn new(condition: ConditionKind, power: Option<u32>, duration: Option<f32>) -> Self {
+        Self {
+            condition,
+            power: power.unwrap_or(FIELD_POWER),
+            duration: duration.unwrap_or(FIELD_DURATION),
+        }
+    }
+}
@@ -1,7 +1,22 @@
+pub mod clock;
 pub mod components;
+pub mod conditions;
+pub mod los;
 pub mod map;
 pub mod messages;
+pub mod spells;
 
 pub const TILE_SIZE: f32 = 32.0;
 
 pub type NetId = u32;
+
+// character names are how characters are saved and found again, so they're
+// kept to letters, digits and single spaces
+pub const MIN_NAME_LENGTH: usize = 3;
+pub const MAX_NAME_LENGTH: usize = 20;
+
+pub fn is_valid_name(name: &str) -> bool {
+    (MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ')
+        && name.split(' ').all(|word| !word.is_empty())
+}
@@ -0,0 +1,110 @@
+// line of sight over tiles, shared so the server only tells a client about
+// what that client's fog lets it see
+
+use std::collections::{HashMap, HashSet};
+
+use glam::IVec2;
+
+// how far anyone can see, in tiles
+pub const SIGHT_RADIUS: i32 = 12;
+
+// the tiles from `from` to `to`, both included (Bresenham)
+pub fn line(from: IVec2, to: IVec2) -> impl Iterator<Item = IVec2> {
+    let delta = (to - from).abs();
+    let step = (to - from).signum();
+    let mut err = delta.x - delta.y;
+    let mut next = Some(from);
+
+    std::iter::from_fn(move || {
+        let pos = next?;
+        if pos == to {
+            next = None;
+            return Some(pos);
+        }
+
+        let mut step_pos = pos;
+        let e2 = 2 * err;
+        if e2 > -delta.y {
+            err -= delta.y;
+            step_pos.x += step.x;
+        }
+        if e2 < delta.x {
+            err += delta.x;
+            step_pos.y += step.y;
+        }
+        next = Some(step_pos);
+
+        Some(pos)
+    })
+}
+
+pub fn in_sight_range(from: IVec2, to: IVec2) -> bool {
+    (to - from).length_squared() <= SIGHT_RADIUS * SIGHT_RADIUS
+}
+
+// only the tiles in between have to be clear, so a wall is seen but nothing
+// behind it. Bresenham takes different tiles going back, so the line is
+// always walked from the same end and sight goes both ways
+pub fn has_line_of_sight(from: IVec2, to: IVec2, mut blocks: impl FnMut(IVec2) -> bool) -> bool {
+    let (from, to) = if (to.y, to.x) < (from.y, from.x) {
+        (to, from)
+    } else {
+        (from, to)
+    };
+    line(from, to)
+        .filter(|&pos| pos != from && pos != to)
+        .all(|pos| !blocks(pos))
+}
+
+pub fn can_see(from: IVec2, to: IVec2, blocks: impl FnMut(IVec2) -> bool) -> bool {
+    in_sight_range(from, to) && has_line_of_sight(from, to, blocks)
+}
+
+// every tile `origin` can see; each tile's blocking is only looked up once
+pub fn visible_tiles(origin: IVec2, mut blocks: impl FnMut(IVec2) -> bool) -> HashSet<IVec2> {
+    let mut cache = HashMap::new();
+    let mut blocked = |pos: IVec2| *cache.entry(pos).or_insert_with(|| blocks(pos));
+
+    let mut visible = HashSet::new();
+    for y in -SIGHT_RADIUS..=SIGHT_RADIUS {
+        for x in -SIGHT_RADIUS..=SIGHT_RADIUS {
+            let pos = origin + IVec2::new(x, y);
+            if can_see(origin, pos, &mut blocked) {
+                visible.insert(pos);
+            }
+        }
+    }
+
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_differ_going_back() {
+        let (a, b) = (IVec2::ZERO, IVec2::new(2, 1));
+        assert!(line(a, b).any(|pos| pos == IVec2::new(1, 0)));
+        assert!(line(b, a).any(|pos| pos == IVec2::new(1, 1)));
+    }
+
+    #[test]
+    fn sight_goes_both_ways_past_a_corner() {
+        let (a, b) = (IVec2::ZERO, IVec2::new(2, 1));
+        for wall in [IVec2::new(1, 0), IVec2::new(1, 1)] {
+            let blocks = |pos| pos == wall;
+            assert_eq!(can_see(a, b, blocks), can_see(b, a, blocks), "{wall}");
+        }
+    }
+
+    #[test]
+    fn sight_is_symmetric_around_walls() {
+        let walls = [IVec2::new(1, 0), IVec2::new(-2, 3), IVec2::new(4, -1)];
+        let blocks = |pos| walls.contains(&pos);
+        let origin = IVec2::ZERO;
+        for pos in visible_tiles(origin, blocks) {
+            assert!(can_see(pos, origin, blocks), "{pos}");
+        }
+    }
+}
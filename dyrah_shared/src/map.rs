@@ -1,8 +1,327 @@
-use std::fs::read_to_string;
+mod bundle;
+mod tmx;
 
-use glam::{IVec2, Vec2};
+pub use bundle::{BundleFile, MapBundle, is_map_name};
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::read_to_string,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use glam::{IVec2, IVec3, UVec2, Vec2};
 use serde::Deserialize;
-use serde_json::from_str;
+use serde_json::{Map as JsonMap, Value, from_str};
+
+use crate::conditions::{ConditionKind, Field};
+
+pub const DEFAULT_MAP: &str = "map";
+
+// maps are stored, collided and streamed in square chunks of this many tiles
+pub const CHUNK_SIZE: i32 = 16;
+
+// the chunk holding a tile and the tile's index within that chunk, using the
+// same +1 Y shift as `TiledLayer::tile_id`
+pub fn chunk_of(tile_pos: IVec2) -> (IVec2, usize) {
+    let pos = tile_pos + IVec2::Y;
+    let chunk = pos.div_euclid(IVec2::splat(CHUNK_SIZE));
+    let local = pos - chunk * CHUNK_SIZE;
+    (chunk, (local.y * CHUNK_SIZE + local.x) as usize)
+}
+
+// Tiled packs flip flags into the high bits of every GID in layer data
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const ROTATED_HEXAGONAL: u32 = 0x1000_0000;
+const FLIP_FLAGS: u32 =
+    FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL;
+
+// rotations are stored as a diagonal flip combined with the other two
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TileFlip {
+    pub horizontal: bool,
+    pub vertical: bool,
+    pub diagonal: bool,
+}
+
+// splits a GID from layer data into the tile it refers to and its flips
+pub fn decode_gid(gid: u32) -> (u32, TileFlip) {
+    let flip = TileFlip {
+        horizontal: gid & FLIPPED_HORIZONTALLY != 0,
+        vertical: gid & FLIPPED_VERTICALLY != 0,
+        diagonal: gid & FLIPPED_DIAGONALLY != 0,
+    };
+    (gid & !FLIP_FLAGS, flip)
+}
+
+// every tile position in a chunk, in the order `chunk_of` indexes them
+pub fn chunk_positions(chunk: IVec2) -> impl Iterator<Item = IVec2> {
+    let origin = chunk * CHUNK_SIZE - IVec2::Y;
+    (0..CHUNK_SIZE).flat_map(move |y| (0..CHUNK_SIZE).map(move |x| origin + IVec2::new(x, y)))
+}
+
+#[derive(Debug)]
+pub enum MapIssue {
+    UnsupportedOrientation(String),
+    MissingExternalTileset {
+        source: String,
+        reason: String,
+    },
+    MissingTilesetImage {
+        tileset: String,
+    },
+    MissingTileSize {
+        tileset: String,
+    },
+    LayerSize {
+        layer: String,
+        width: u32,
+        height: u32,
+    },
+    LayerDataLength {
+        layer: String,
+        len: usize,
+    },
+    BadLayerData {
+        layer: String,
+        reason: String,
+    },
+    UnknownTile {
+        layer: String,
+        gid: u32,
+    },
+    UnknownAnimationFrame {
+        tileset: String,
+        tile: u32,
+        frame: u32,
+    },
+    ObjectOutOfBounds {
+        layer: String,
+        object: String,
+    },
+}
+
+impl fmt::Display for MapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedOrientation(o) => {
+                write!(f, "{} orientation is unsupported, use orthogonal", o)
+            }
+            Self::MissingExternalTileset { source, reason } => {
+                write!(
+                    f,
+                    "external tileset {} couldn't be loaded: {}",
+                    source, reason
+                )
+            }
+            Self::BadLayerData { layer, reason } => {
+                write!(f, "layer {} has unreadable data: {}", layer, reason)
+            }
+            Self::MissingTilesetImage { tileset } => {
+                write!(f, "tileset {} has no image", tileset)
+            }
+            Self::MissingTileSize { tileset } => {
+                write!(f, "tileset {} has no tile width/height", tileset)
+            }
+            Self::LayerSize {
+                layer,
+                width,
+                height,
+            } => write!(
+                f,
+                "layer {} is {}x{} but should match the map size",
+                layer, width, height
+            ),
+            Self::LayerDataLength { layer, len } => {
+                write!(
+                    f,
+                    "layer {} has {} tiles, which doesn't fit its size",
+                    layer, len
+                )
+            }
+            Self::UnknownTile { layer, gid } => {
+                write!(f, "layer {} uses tile {} that no tileset has", layer, gid)
+            }
+            Self::UnknownAnimationFrame {
+                tileset,
+                tile,
+                frame,
+            } => write!(
+                f,
+                "tile {} in tileset {} animates to tile {} that the tileset doesn't have",
+                tile, tileset, frame
+            ),
+            Self::ObjectOutOfBounds { layer, object } => {
+                write!(f, "object {} in layer {} is outside the map", object, layer)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Xml(roxmltree::Error),
+    Format(String),
+    Invalid(Vec<MapIssue>),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read map: {}", e),
+            Self::Json(e) => write!(f, "failed to parse map: {}", e),
+            Self::Xml(e) => write!(f, "failed to parse map: {}", e),
+            Self::Format(e) => write!(f, "malformed map: {}", e),
+            Self::Invalid(issues) => {
+                write!(f, "map has {} problem(s):", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Xml(e) => Some(e),
+            Self::Format(_) | Self::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<roxmltree::Error> for MapError {
+    fn from(e: roxmltree::Error) -> Self {
+        Self::Xml(e)
+    }
+}
+
+// every Tiled JSON or TMX map in `dir`, named by file stem
+pub fn list_maps(dir: &str) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = Path::new(dir).read_dir() else {
+        return Vec::new();
+    };
+
+    let mut maps: Vec<(String, PathBuf)> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|ext| ext == "json" || ext == "tmx")
+        })
+        .filter_map(|p| Some((p.file_stem()?.to_str()?.to_string(), p)))
+        .collect();
+    maps.sort();
+    maps
+}
+
+// joins `path` onto `base` and folds away any ".." so that paths found in
+// external tilesets stay relative to the map's directory
+fn join_relative(base: &Path, path: &str) -> String {
+    let joined = base.join(path);
+    let mut parts: Vec<&str> = Vec::new();
+
+    for component in joined.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().unwrap_or_default()),
+            Component::ParentDir if parts.last().is_some_and(|&p| p != "..") => {
+                parts.pop();
+            }
+            Component::ParentDir => parts.push(".."),
+            _ => {}
+        }
+    }
+
+    parts.join("/")
+}
+
+// layer data is either CSV or little-endian u32s in base64, optionally compressed
+fn decode_tile_data(
+    data: &str,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Vec<u32>, String> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(|gid| {
+                gid.trim()
+                    .parse()
+                    .map_err(|e| format!("bad tile {}: {}", gid, e))
+            })
+            .collect(),
+        Some("base64") => {
+            let cleaned: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+            let bytes = BASE64.decode(cleaned).map_err(|e| e.to_string())?;
+
+            let mut raw = Vec::new();
+            match compression {
+                None | Some("") => raw = bytes,
+                Some("zlib") => {
+                    ZlibDecoder::new(&bytes[..])
+                        .read_to_end(&mut raw)
+                        .map_err(|e| e.to_string())?;
+                }
+                Some("gzip") => {
+                    GzDecoder::new(&bytes[..])
+                        .read_to_end(&mut raw)
+                        .map_err(|e| e.to_string())?;
+                }
+                Some("zstd") => raw = zstd::decode_all(&bytes[..]).map_err(|e| e.to_string())?,
+                Some(other) => return Err(format!("unsupported compression {}", other)),
+            }
+
+            if raw.len() % 4 != 0 {
+                return Err("data isn't a whole number of tiles".to_string());
+            }
+            Ok(raw
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        Some(other) => Err(format!("unsupported encoding {}", other)),
+        None => Err("encoded data without an encoding".to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TileBlocking {
+    pub walk: bool,
+    pub projectiles: bool,
+    pub sight: bool,
+}
+
+impl TileBlocking {
+    pub const ALL: Self = Self {
+        walk: true,
+        projectiles: true,
+        sight: true,
+    };
+}
 
 #[derive(Deserialize, Debug)]
 pub struct TileOffset {
@@ -10,6 +329,161 @@ pub struct TileOffset {
     pub y: i32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TiledColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl TiledColor {
+    // Tiled writes colors as #AARRGGBB, or #RRGGBB when fully opaque
+    pub fn parse(s: &str) -> Option<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let argb = match hex.len() {
+            6 => 0xff000000 | u32::from_str_radix(hex, 16).ok()?,
+            8 => u32::from_str_radix(hex, 16).ok()?,
+            _ => return None,
+        };
+        let [a, r, g, b] = argb.to_be_bytes();
+
+        Some(Self { r, g, b, a })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Color(Option<TiledColor>),
+    File(String),
+    Object(u32),
+    Class(JsonMap<String, Value>),
+}
+
+#[derive(Deserialize)]
+struct RawProperty {
+    name: String,
+    #[serde(rename = "type", default = "default_property_type")]
+    kind: String,
+    value: Value,
+}
+
+fn default_property_type() -> String {
+    "string".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawProperty")]
+pub struct TiledProperty {
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+impl TryFrom<RawProperty> for TiledProperty {
+    type Error = String;
+
+    fn try_from(raw: RawProperty) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid {} value for property {}", raw.kind, raw.name);
+        let value = match raw.kind.as_str() {
+            "bool" => PropertyValue::Bool(raw.value.as_bool().ok_or_else(invalid)?),
+            "int" => PropertyValue::Int(raw.value.as_i64().ok_or_else(invalid)?),
+            "float" => PropertyValue::Float(raw.value.as_f64().ok_or_else(invalid)?),
+            "string" => PropertyValue::String(raw.value.as_str().ok_or_else(invalid)?.to_string()),
+            "file" => PropertyValue::File(raw.value.as_str().ok_or_else(invalid)?.to_string()),
+            "object" => PropertyValue::Object(raw.value.as_u64().ok_or_else(invalid)? as u32),
+            "color" => {
+                let s = raw.value.as_str().ok_or_else(invalid)?;
+                // an empty string is an unset color
+                PropertyValue::Color(match s {
+                    "" => None,
+                    _ => Some(TiledColor::parse(s).ok_or_else(invalid)?),
+                })
+            }
+            "class" => PropertyValue::Class(raw.value.as_object().cloned().unwrap_or_default()),
+            kind => return Err(format!("Unknown type {} for property {}", kind, raw.name)),
+        };
+
+        Ok(Self {
+            name: raw.name,
+            value,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Properties(Vec<TiledProperty>);
+
+impl Properties {
+    pub fn get(&self, name: &str) -> Option<&PropertyValue> {
+        self.0.iter().find(|p| p.name == name).map(|p| &p.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TiledProperty> {
+        self.0.iter()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            PropertyValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            PropertyValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            PropertyValue::Float(f) => Some(*f),
+            PropertyValue::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            PropertyValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_color(&self, name: &str) -> Option<TiledColor> {
+        match self.get(name)? {
+            PropertyValue::Color(c) => *c,
+            _ => None,
+        }
+    }
+
+    pub fn get_file(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            PropertyValue::File(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn get_object(&self, name: &str) -> Option<u32> {
+        match self.get(name)? {
+            PropertyValue::Object(id) if *id != 0 => Some(*id),
+            _ => None,
+        }
+    }
+
+    pub fn get_class(&self, name: &str) -> Option<&JsonMap<String, Value>> {
+        match self.get(name)? {
+            PropertyValue::Class(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct TiledObject {
     pub id: u32,
@@ -18,6 +492,57 @@ pub struct TiledObject {
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    #[serde(default, rename = "type", alias = "class")]
+    pub class: String,
+    #[serde(default)]
+    pub properties: Properties,
+    // set on tile objects, which Tiled anchors by their bottom-left corner
+    #[serde(default)]
+    pub gid: Option<u32>,
+    #[serde(skip)]
+    pub floor: i32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RawTileData {
+    Tiles(Vec<u32>),
+    Encoded(String),
+}
+
+// a rectangle of tiles in an infinite layer, positioned in tiles
+#[derive(Deserialize, Debug)]
+pub struct TiledChunk {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, rename = "data")]
+    raw_data: Option<RawTileData>,
+    #[serde(skip)]
+    pub data: Vec<u32>,
+}
+
+impl TiledChunk {
+    pub fn new(x: i32, y: i32, width: u32, height: u32, data: Vec<u32>) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            raw_data: None,
+            data,
+        }
+    }
+
+    fn tile_id(&self, x: i32, y: i32) -> Option<u32> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+
+        self.data.get((y * self.width as i32 + x) as usize).copied()
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -26,19 +551,280 @@ pub struct TiledLayer {
     pub height: Option<u32>,
     pub name: String,
     pub visible: bool,
+    #[serde(default, rename = "data")]
+    raw_data: Option<RawTileData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(skip)]
     pub data: Option<Vec<u32>>,
+    pub chunks: Option<Vec<TiledChunk>>,
+    // where each chunk is in `chunks`, by its position in chunks, so finding
+    // a tile doesn't mean going through all of them; Tiled's chunks are all
+    // the same size within a layer and lined up on it
+    #[serde(skip)]
+    chunk_index: HashMap<IVec2, usize>,
+    #[serde(skip)]
+    chunk_size: IVec2,
     pub objects: Option<Vec<TiledObject>>,
+    pub layers: Option<Vec<TiledLayer>>,
+    #[serde(default)]
+    pub properties: Properties,
+    #[serde(skip)]
+    pub floor: i32,
+}
+
+impl TiledLayer {
+    pub fn is_tile_layer(&self) -> bool {
+        self.data.is_some() || self.chunks.is_some()
+    }
+
+    pub fn tile_id(&self, tile_pos: IVec2) -> Option<u32> {
+        // shift Y by +1 tile to compensate for the rendering offset
+        let (x, y) = (tile_pos.x, tile_pos.y + 1);
+
+        // infinite layers are simply empty wherever there's no chunk
+        if let Some(chunks) = &self.chunks {
+            let key = IVec2::new(x, y).div_euclid(self.chunk_size.max(IVec2::ONE));
+            let chunk = self.chunk_index.get(&key).map(|&i| &chunks[i]);
+            return Some(chunk.and_then(|c| c.tile_id(x, y)).unwrap_or(0));
+        }
+
+        let (width, height) = (self.width? as i32, self.height? as i32);
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None;
+        }
+
+        self.data.as_ref()?.get((y * width + x) as usize).copied()
+    }
+
+    // has to be called whenever `chunks` changes
+    fn index_chunks(&mut self) {
+        self.chunk_index.clear();
+        let Some(chunks) = &self.chunks else {
+            return;
+        };
+        self.chunk_size = chunks.first().map_or(IVec2::ONE, |c| {
+            IVec2::new(c.width as i32, c.height as i32).max(IVec2::ONE)
+        });
+        for (i, chunk) in chunks.iter().enumerate() {
+            let key = IVec2::new(chunk.x, chunk.y).div_euclid(self.chunk_size);
+            self.chunk_index.insert(key, i);
+        }
+    }
+
+    // every non-empty tile with its position in the layer (without the Y shift)
+    pub fn tiles(&self) -> impl Iterator<Item = (IVec2, u32)> + '_ {
+        let width = self.width.unwrap_or(0).max(1) as i32;
+        let data = self.data.iter().flat_map(move |data| {
+            data.iter()
+                .enumerate()
+                .map(move |(i, &gid)| (IVec2::new(i as i32 % width, i as i32 / width), gid))
+        });
+        let chunks = self.chunks.iter().flatten().flat_map(|c| {
+            let width = c.width.max(1) as i32;
+            c.data.iter().enumerate().map(move |(i, &gid)| {
+                (
+                    IVec2::new(c.x + i as i32 % width, c.y + i as i32 / width),
+                    gid,
+                )
+            })
+        });
+
+        data.chain(chunks).filter(|&(_, gid)| gid != 0)
+    }
+}
+
+fn decode_layer_data(
+    raw: Option<RawTileData>,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Option<Vec<u32>>, String> {
+    match raw {
+        Some(RawTileData::Tiles(data)) => Ok(Some(data)),
+        Some(RawTileData::Encoded(data)) => {
+            decode_tile_data(&data, encoding, compression).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+// group layers are flattened into their children, which inherit the group's
+// floor (the "floor" property) and visibility
+fn flatten_layers(
+    layers: Vec<TiledLayer>,
+    floor: i32,
+    visible: bool,
+    issues: &mut Vec<MapIssue>,
+) -> Vec<TiledLayer> {
+    let mut flat = Vec::new();
+
+    for mut layer in layers {
+        let (encoding, compression) = (layer.encoding.as_deref(), layer.compression.as_deref());
+        let mut result = decode_layer_data(layer.raw_data.take(), encoding, compression)
+            .map(|data| layer.data = data);
+        for chunk in layer.chunks.iter_mut().flatten() {
+            result = result.and(
+                decode_layer_data(chunk.raw_data.take(), encoding, compression)
+                    .map(|data| chunk.data = data.unwrap_or_default()),
+            );
+        }
+        if let Err(reason) = result {
+            issues.push(MapIssue::BadLayerData {
+                layer: layer.name.clone(),
+                reason,
+            });
+        }
+
+        let floor = layer
+            .properties
+            .get_int("floor")
+            .map_or(floor, |f| f as i32);
+        let visible = visible && layer.visible;
+
+        match layer.layers.take() {
+            Some(children) => flat.extend(flatten_layers(children, floor, visible, issues)),
+            None => {
+                layer.floor = floor;
+                layer.visible = visible;
+                layer.index_chunks();
+                for object in layer.objects.iter_mut().flatten() {
+                    object.floor = floor;
+                }
+                flat.push(layer);
+            }
+        }
+    }
+
+    flat
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct TiledFrame {
+    pub tileid: u32,
+    pub duration: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledTile {
+    pub id: u32,
+    #[serde(default, rename = "type", alias = "class")]
+    pub class: String,
+    #[serde(default)]
+    pub properties: Properties,
+    #[serde(default)]
+    pub animation: Vec<TiledFrame>,
+}
+
+impl TiledTile {
+    // the local tile id shown `elapsed_ms` into the looping animation
+    pub fn frame_at(&self, elapsed_ms: u64) -> u32 {
+        let total: u64 = self.animation.iter().map(|f| f.duration as u64).sum();
+        if total == 0 {
+            return self.id;
+        }
+
+        let mut time = elapsed_ms % total;
+        for frame in &self.animation {
+            if time < frame.duration as u64 {
+                return frame.tileid;
+            }
+            time -= frame.duration as u64;
+        }
+
+        self.id
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TiledTileset {
+    #[serde(default)]
     pub firstgid: u32,
+    pub name: Option<String>,
     pub source: Option<String>,
     pub image: Option<String>,
     pub tilecount: Option<u32>,
     pub tilewidth: Option<u32>,
     pub tileheight: Option<u32>,
+    pub imagewidth: Option<u32>,
+    pub imageheight: Option<u32>,
+    #[serde(default)]
+    pub margin: u32,
+    #[serde(default)]
+    pub spacing: u32,
     pub tileoffset: Option<TileOffset>,
+    #[serde(default)]
+    pub tiles: Vec<TiledTile>,
+    #[serde(default)]
+    pub properties: Properties,
+    #[serde(skip)]
+    resolved: bool,
+}
+
+impl TiledTileset {
+    pub fn get_tile(&self, local_id: u32) -> Option<&TiledTile> {
+        self.tiles.iter().find(|t| t.id == local_id)
+    }
+
+    // external tilesets only carry a firstgid and source until resolved
+    pub fn is_resolved(&self) -> bool {
+        self.source.is_none() || self.resolved
+    }
+
+    // Tiled usually writes the count, otherwise it's worked out from how many
+    // tiles fit in the image; None when there's no way to tell
+    pub fn tile_count(&self) -> Option<u32> {
+        if self.tilecount.is_some() {
+            return self.tilecount;
+        }
+        let grid = self.grid(self.imagewidth?, self.imageheight?)?;
+        Some(grid.x * grid.y)
+    }
+
+    // the columns and rows of tiles that fit in an image this size, going by
+    // the margin around them and the spacing between them
+    pub fn grid(&self, image_width: u32, image_height: u32) -> Option<UVec2> {
+        let fit = |image: u32, tile: u32| {
+            (image + self.spacing).saturating_sub(2 * self.margin) / (tile + self.spacing).max(1)
+        };
+        Some(UVec2::new(
+            fit(image_width, self.tilewidth?),
+            fit(image_height, self.tileheight?),
+        ))
+    }
+
+    // the top left pixel of a tile in an image `columns` tiles wide
+    pub fn tile_origin(&self, local_id: u32, columns: u32) -> Option<UVec2> {
+        let columns = columns.max(1);
+        let cell = UVec2::new(self.tilewidth?, self.tileheight?) + self.spacing;
+        Some(UVec2::splat(self.margin) + UVec2::new(local_id % columns, local_id / columns) * cell)
+    }
+
+    fn resolve(&mut self, dir: Option<&Path>) -> Result<(), String> {
+        let Some(source) = self.source.clone() else {
+            return Ok(());
+        };
+        let dir = dir.ok_or("the map was loaded without a path")?;
+        let content = read_to_string(dir.join(&source)).map_err(|e| e.to_string())?;
+
+        let mut tileset = match Path::new(&source).extension().and_then(|e| e.to_str()) {
+            Some("tsx") => tmx::parse_tileset(&content).map_err(|e| e.to_string())?,
+            _ => from_str::<TiledTileset>(&content).map_err(|e| e.to_string())?,
+        };
+
+        // images are relative to the tileset file, not the map
+        let source_dir = Path::new(&source).parent().unwrap_or(Path::new(""));
+        tileset.image = tileset.image.map(|image| join_relative(source_dir, &image));
+        tileset.firstgid = self.firstgid;
+        tileset.source = Some(source);
+        tileset.resolved = true;
+
+        *self = tileset;
+        Ok(())
+    }
+}
+
+fn default_orientation() -> String {
+    "orthogonal".to_string()
 }
 
 #[derive(Deserialize)]
@@ -47,44 +833,395 @@ pub struct TiledMap {
     pub height: u32,
     pub tilewidth: u32,
     pub tileheight: u32,
+    #[serde(default = "default_orientation")]
+    pub orientation: String,
+    #[serde(default)]
+    pub infinite: bool,
     pub layers: Vec<TiledLayer>,
     pub tilesets: Vec<TiledTileset>,
+    #[serde(default)]
+    pub properties: Properties,
 }
 
 impl TiledMap {
-    pub fn new(path: &str) -> Self {
-        let content = read_to_string(path).expect("Failed to read map file");
-        from_str(&content).expect("Failed to parse JSON map")
+    // loads a JSON or TMX map, along with any external tilesets it references
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MapError> {
+        let path = path.as_ref();
+        let content = read_to_string(path)?;
+        let map = match path.extension().and_then(|e| e.to_str()) {
+            Some("tmx") => tmx::parse_map(&content)?,
+            _ => from_str(&content)?,
+        };
+
+        map.finish(path.parent())
+    }
+
+    // parses a JSON map on its own, so every tileset has to be embedded
+    pub fn parse(content: &str) -> Result<Self, MapError> {
+        from_str::<Self>(content)?.finish(None)
+    }
+
+    fn finish(mut self, dir: Option<&Path>) -> Result<Self, MapError> {
+        let mut issues = Vec::new();
+
+        self.layers = flatten_layers(std::mem::take(&mut self.layers), 0, true, &mut issues);
+        for tileset in &mut self.tilesets {
+            if let Err(reason) = tileset.resolve(dir) {
+                issues.push(MapIssue::MissingExternalTileset {
+                    source: tileset.source.clone().unwrap_or_default(),
+                    reason,
+                });
+            }
+        }
+
+        issues.extend(self.validate());
+        if !issues.is_empty() {
+            return Err(MapError::Invalid(issues));
+        }
+
+        Ok(self)
+    }
+
+    // collects every problem at once so mappers can fix them in one go
+    pub fn validate(&self) -> Vec<MapIssue> {
+        let mut issues = Vec::new();
+
+        if self.orientation != "orthogonal" {
+            issues.push(MapIssue::UnsupportedOrientation(self.orientation.clone()));
+        }
+
+        let mut max_gid = 0;
+        // tiles past the last tileset can't be told apart from tiles of a
+        // tileset whose size isn't known
+        let mut sizes_known = true;
+        for tileset in &self.tilesets {
+            // unresolved external tilesets were already reported while loading
+            if !tileset.is_resolved() {
+                continue;
+            }
+
+            let name = tileset
+                .name
+                .clone()
+                .unwrap_or_else(|| tileset.firstgid.to_string());
+            if tileset.image.is_none() {
+                issues.push(MapIssue::MissingTilesetImage {
+                    tileset: name.clone(),
+                });
+            }
+            if tileset.tilewidth.is_none() || tileset.tileheight.is_none() {
+                issues.push(MapIssue::MissingTileSize {
+                    tileset: name.clone(),
+                });
+            }
+
+            let Some(tilecount) = tileset.tile_count() else {
+                sizes_known = false;
+                continue;
+            };
+            for tile in &tileset.tiles {
+                if let Some(frame) = tile.animation.iter().find(|f| f.tileid >= tilecount) {
+                    issues.push(MapIssue::UnknownAnimationFrame {
+                        tileset: name.clone(),
+                        tile: tile.id,
+                        frame: frame.tileid,
+                    });
+                }
+            }
+
+            max_gid = max_gid.max(tileset.firstgid + tilecount);
+        }
+
+        // unresolved tilesets have no tilecount to check against either
+        let has_external = self.tilesets.iter().any(|t| !t.is_resolved()) || !sizes_known;
+        let map_size = (self.width * self.tilewidth, self.height * self.tileheight);
+        for layer in &self.layers {
+            if let Some(data) = &layer.data {
+                let (width, height) = (layer.width.unwrap_or(0), layer.height.unwrap_or(0));
+                if self.infinite || (width, height) != (self.width, self.height) {
+                    issues.push(MapIssue::LayerSize {
+                        layer: layer.name.clone(),
+                        width,
+                        height,
+                    });
+                }
+                if data.len() != (width * height) as usize {
+                    issues.push(MapIssue::LayerDataLength {
+                        layer: layer.name.clone(),
+                        len: data.len(),
+                    });
+                }
+            }
+            for chunk in layer.chunks.iter().flatten() {
+                if chunk.data.len() != (chunk.width * chunk.height) as usize {
+                    issues.push(MapIssue::LayerDataLength {
+                        layer: layer.name.clone(),
+                        len: chunk.data.len(),
+                    });
+                }
+            }
+
+            let mut gids = layer.tiles().map(|(_, gid)| decode_gid(gid).0);
+            if !has_external && let Some(gid) = gids.find(|&gid| gid >= max_gid) {
+                issues.push(MapIssue::UnknownTile {
+                    layer: layer.name.clone(),
+                    gid,
+                });
+            }
+
+            for object in layer.objects.iter().flatten() {
+                // infinite maps have no edges for objects to fall off
+                let (x, y) = (object.x, object.y);
+                let outside = x < 0.0 || y < 0.0 || x > map_size.0 as f32 || y > map_size.1 as f32;
+                if !self.infinite && outside {
+                    let object = match object.name.is_empty() {
+                        true => format!("#{}", object.id),
+                        false => object.name.clone(),
+                    };
+                    issues.push(MapIssue::ObjectOutOfBounds {
+                        layer: layer.name.clone(),
+                        object,
+                    });
+                }
+            }
+        }
+
+        issues
     }
 
     pub fn get_layer(&self, layer_name: &str) -> Option<&TiledLayer> {
         self.layers.iter().find(|l| l.name == layer_name)
     }
 
+    // both accept GIDs straight from layer data, flip flags and all
+    pub fn get_tileset(&self, gid: u32) -> Option<&TiledTileset> {
+        let (gid, _) = decode_gid(gid);
+        self.tilesets.iter().rev().find(|set| gid >= set.firstgid)
+    }
+
+    pub fn tile_properties(&self, gid: u32) -> Option<&Properties> {
+        let (gid, _) = decode_gid(gid);
+        let tileset = self.get_tileset(gid)?;
+        tileset
+            .get_tile(gid - tileset.firstgid)
+            .map(|t| &t.properties)
+    }
+
+    // the GID to draw for an animated tile `elapsed_ms` in, keeping its flips;
+    // every copy of a tile shares the clock so they all animate in sync
+    pub fn animated_gid(&self, gid: u32, elapsed_ms: u64) -> u32 {
+        let (id, _) = decode_gid(gid);
+        let Some(tileset) = self.get_tileset(id) else {
+            return gid;
+        };
+
+        match tileset.get_tile(id - tileset.firstgid) {
+            Some(tile) if !tile.animation.is_empty() => {
+                (tileset.firstgid + tile.frame_at(elapsed_ms)) | (gid & FLIP_FLAGS)
+            }
+            _ => gid,
+        }
+    }
+
+    pub fn get_layers<'a>(&'a self, layer_name: &str) -> impl Iterator<Item = &'a TiledLayer> {
+        self.layers.iter().filter(move |l| l.name == layer_name)
+    }
+
     pub fn get_object(&self, layer_name: &str, name: &str) -> Option<&TiledObject> {
-        self.get_layer(layer_name)
-            .and_then(|l| l.objects.as_ref()?.iter().find(|o| o.name == name))
+        self.get_layers(layer_name)
+            .find_map(|l| l.objects.as_ref()?.iter().find(|o| o.name == name))
     }
 
-    pub fn is_walkable(&self, layer_name: &str, tile_pos: IVec2) -> bool {
-        let layer = match self.get_layer(layer_name) {
-            Some(l) => l,
-            None => return true,
+    // the tiles under an object's rectangle as min and max (exclusive); point
+    // and small objects still cover the tile they're on. objects are placed in
+    // Tiled's pixels, so the row is found there and then moved up one like
+    // every tile position is (see TiledLayer::tile_id)
+    pub fn object_tiles(&self, object: &TiledObject) -> (IVec2, IVec2) {
+        let top = match object.gid {
+            Some(_) => object.y - object.height,
+            None => object.y,
         };
+        let min = IVec2::new(
+            (object.x / self.tilewidth as f32).floor() as i32,
+            (top / self.tileheight as f32).floor() as i32,
+        ) - IVec2::Y;
+        let size = IVec2::new(
+            (object.width / self.tilewidth as f32).max(1.0) as i32,
+            (object.height / self.tileheight as f32).max(1.0) as i32,
+        );
+        (min, min + size)
+    }
 
-        // shift Y by +1 tile to compensate for the rendering offset
-        let tile_x = tile_pos.x as usize;
-        let tile_y = (tile_pos.y + 1) as usize;
-        if tile_x >= layer.width.unwrap() as usize || tile_y >= layer.height.unwrap() as usize {
-            return false; // out of bounds = blocked
+    pub fn object_contains(&self, object: &TiledObject, tile_pos: IVec2) -> bool {
+        let (min, max) = self.object_tiles(object);
+        tile_pos.cmpge(min).all() && tile_pos.cmplt(max).all()
+    }
+
+    // building interiors are rectangles in "interiors" object layers
+    pub fn interior_at(&self, tile_pos: IVec3) -> Option<&TiledObject> {
+        self.get_layers("interiors")
+            .filter(|l| l.floor == tile_pos.z)
+            .flat_map(|l| l.objects.iter().flatten())
+            .find(|o| self.object_contains(o, tile_pos.truncate()))
+    }
+
+    pub fn floors(&self) -> Vec<i32> {
+        let mut floors: Vec<i32> = self.layers.iter().map(|l| l.floor).collect();
+        floors.sort();
+        floors.dedup();
+        floors
+    }
+
+    fn tile_layers_on(&self, floor: i32) -> impl Iterator<Item = &TiledLayer> {
+        self.layers
+            .iter()
+            .filter(move |l| l.floor == floor && l.is_tile_layer())
+    }
+
+    // every chunk with tiles in it; finite maps are covered edge to edge
+    pub fn chunks(&self) -> Vec<IVec2> {
+        let mut rects: Vec<(IVec2, IVec2)> = Vec::new();
+        if !self.infinite {
+            rects.push((
+                IVec2::ZERO,
+                IVec2::new(self.width as i32, self.height as i32),
+            ));
+        }
+        for chunk in self.layers.iter().flat_map(|l| l.chunks.iter().flatten()) {
+            let min = IVec2::new(chunk.x, chunk.y);
+            rects.push((
+                min,
+                min + IVec2::new(chunk.width as i32, chunk.height as i32),
+            ));
         }
 
-        let index = tile_y * layer.width.unwrap() as usize + tile_x;
-        layer
-            .data
-            .as_ref()
-            .and_then(|data| data.get(index))
-            .map_or(false, |&tile| tile == 0)
+        let mut chunks = Vec::new();
+        for (min, max) in rects.into_iter().filter(|(min, max)| min.cmplt(*max).all()) {
+            let min = min.div_euclid(IVec2::splat(CHUNK_SIZE));
+            let max = (max - 1).div_euclid(IVec2::splat(CHUNK_SIZE));
+            for y in min.y..=max.y {
+                chunks.extend((min.x..=max.x).map(|x| IVec2::new(x, y)));
+            }
+        }
+        chunks.sort_by_key(|c| (c.y, c.x));
+        chunks.dedup();
+        chunks
+    }
+
+    // the tiles of every layer with something in this chunk, by layer index
+    pub fn read_chunk(&self, chunk: IVec2) -> Vec<(u32, Vec<u32>)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.is_tile_layer())
+            .filter_map(|(i, layer)| {
+                let data: Vec<u32> = chunk_positions(chunk)
+                    .map(|pos| layer.tile_id(pos).unwrap_or(0))
+                    .collect();
+                data.iter().any(|&gid| gid != 0).then_some((i as u32, data))
+            })
+            .collect()
+    }
+
+    // streamed chunks replace whatever the layer had there before
+    pub fn write_chunk(&mut self, layer: u32, chunk: IVec2, data: Vec<u32>) {
+        let Some(layer) = self.layers.get_mut(layer as usize) else {
+            return;
+        };
+        let origin = chunk * CHUNK_SIZE;
+        let chunks = layer.chunks.get_or_insert_default();
+
+        chunks.retain(|c| (c.x, c.y) != (origin.x, origin.y));
+        let size = CHUNK_SIZE as u32;
+        chunks.push(TiledChunk::new(origin.x, origin.y, size, size, data));
+        layer.index_chunks();
+    }
+
+    pub fn remove_chunk(&mut self, chunk: IVec2) {
+        let origin = chunk * CHUNK_SIZE;
+        for layer in &mut self.layers {
+            if let Some(chunks) = &mut layer.chunks {
+                chunks.retain(|c| (c.x, c.y) != (origin.x, origin.y));
+                layer.index_chunks();
+            }
+        }
+    }
+
+    // drops every chunk so an infinite map can be filled in by streaming
+    pub fn clear_chunks(&mut self) {
+        for layer in &mut self.layers {
+            if let Some(chunks) = &mut layer.chunks {
+                chunks.clear();
+                layer.index_chunks();
+            }
+        }
+    }
+
+    // merges the blocking properties of every tile stacked on this position;
+    // tiles without ground or painted in the collider layer can't be walked on
+    pub fn blocking(&self, collider_layer: &str, tile_pos: IVec3) -> TileBlocking {
+        let pos = tile_pos.truncate();
+        let mut blocking = TileBlocking::default();
+        let mut has_ground = false;
+
+        for layer in self.tile_layers_on(tile_pos.z) {
+            match layer.tile_id(pos) {
+                None => return TileBlocking::ALL, // out of bounds = blocked
+                Some(0) => {}
+                Some(_) if layer.name == collider_layer => blocking.walk = true,
+                Some(gid) => {
+                    has_ground = true;
+
+                    if let Some(props) = self.tile_properties(gid) {
+                        blocking.walk |= props.get_bool("blocking").unwrap_or(false);
+                        blocking.projectiles |=
+                            props.get_bool("blocks_projectiles").unwrap_or(false);
+                        blocking.sight |= props.get_bool("blocks_sight").unwrap_or(false);
+                    }
+                }
+            }
+        }
+
+        blocking.walk |= !has_ground;
+        blocking
+    }
+
+    pub fn is_walkable(&self, collider_layer: &str, tile_pos: IVec3) -> bool {
+        !self.blocking(collider_layer, tile_pos).walk
+    }
+
+    // stairs, ladders and holes are tile layers with a "floor_change" property
+    pub fn floor_change(&self, tile_pos: IVec3) -> Option<i32> {
+        self.tile_layers_on(tile_pos.z).find_map(|l| {
+            let change = l.properties.get_int("floor_change")?;
+            l.tile_id(tile_pos.truncate())
+                .filter(|&id| id != 0)
+                .map(|_| change as i32)
+        })
+    }
+
+    // fire, poison and energy fields are tiles with a "field" property naming
+    // the condition they give, and optionally its "power" and "duration"
+    pub fn field_at(&self, tile_pos: IVec3) -> Option<Field> {
+        self.tile_layers_on(tile_pos.z).find_map(|l| {
+            let gid = l.tile_id(tile_pos.truncate()).filter(|&id| id != 0)?;
+            let props = self.tile_properties(gid)?;
+            let condition = ConditionKind::from_name(props.get_string("field")?)?;
+            Some(Field::new(
+                condition,
+                props.get_int("power").map(|p| p as u32),
+                props.get_float("duration").map(|d| d as f32),
+            ))
+        })
+    }
+
+    // anything drawn on a higher floor above this tile hides the floors above
+    pub fn is_covered(&self, tile_pos: IVec3) -> bool {
+        self.layers.iter().any(|l| {
+            l.floor > tile_pos.z
+                && l.visible
+                && l.tile_id(tile_pos.truncate()).is_some_and(|id| id != 0)
+        })
     }
 
     pub fn tile_to_world(&self, tile_pos: IVec2) -> Vec2 {
@@ -119,3 +1256,396 @@ impl TiledMap {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn converts_each_property_type() {
+        let properties: Properties = serde_json::from_value(json!([
+            { "name": "solid", "type": "bool", "value": true },
+            { "name": "floor", "type": "int", "value": -2 },
+            { "name": "ambient", "type": "float", "value": 0.25 },
+            { "name": "title", "type": "string", "value": "Cave" },
+            { "name": "untyped", "value": "plain" },
+            { "name": "tint", "type": "color", "value": "#80ff0000" },
+            { "name": "unset", "type": "color", "value": "" },
+            { "name": "music", "type": "file", "value": "cave.ogg" },
+            { "name": "target", "type": "object", "value": 7 },
+            { "name": "nothing", "type": "object", "value": 0 },
+            { "name": "stats", "type": "class", "value": { "hp": 3 } }
+        ]))
+        .unwrap();
+
+        assert_eq!(properties.get_bool("solid"), Some(true));
+        assert_eq!(properties.get_int("floor"), Some(-2));
+        assert_eq!(properties.get_float("ambient"), Some(0.25));
+        assert_eq!(properties.get_float("floor"), Some(-2.0));
+        assert_eq!(properties.get_string("title"), Some("Cave"));
+        assert_eq!(properties.get_string("untyped"), Some("plain"));
+        let tint = TiledColor {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 0x80,
+        };
+        assert_eq!(properties.get_color("tint"), Some(tint));
+        assert_eq!(properties.get("unset"), Some(&PropertyValue::Color(None)));
+        assert_eq!(properties.get_file("music"), Some("cave.ogg"));
+        assert_eq!(properties.get_object("target"), Some(7));
+        assert_eq!(properties.get_object("nothing"), None);
+        assert_eq!(properties.get_class("stats").unwrap()["hp"], json!(3));
+        // asking for the wrong type finds nothing
+        assert_eq!(properties.get_int("title"), None);
+    }
+
+    #[test]
+    fn parses_opaque_colors() {
+        let green = TiledColor {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        };
+        assert_eq!(TiledColor::parse("#00ff00"), Some(green));
+        assert_eq!(TiledColor::parse("#0f0"), None);
+    }
+
+    #[test]
+    fn rejects_mismatched_property_values() {
+        let error = serde_json::from_value::<TiledProperty>(
+            json!({ "name": "floor", "type": "int", "value": "two" }),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid int value for property floor")
+        );
+
+        let error = serde_json::from_value::<TiledProperty>(
+            json!({ "name": "tint", "type": "color", "value": "red" }),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid color value for property tint")
+        );
+
+        let error = serde_json::from_value::<TiledProperty>(
+            json!({ "name": "odd", "type": "vector", "value": 1 }),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Unknown type vector for property odd")
+        );
+    }
+
+    // a distinct tile for each position of the layer, without the Y shift
+    fn infinite_gid(pos: IVec2) -> u32 {
+        (pos.x.rem_euclid(7) + pos.y.rem_euclid(5) * 7) as u32 + 1
+    }
+
+    // an infinite map with chunks at (-1, -1) and (0, 0)
+    fn infinite_map() -> TiledMap {
+        let chunk = |origin: IVec2| {
+            let data: Vec<u32> = (0..CHUNK_SIZE * CHUNK_SIZE)
+                .map(|i| infinite_gid(origin + IVec2::new(i % CHUNK_SIZE, i / CHUNK_SIZE)))
+                .collect();
+            format!(
+                r#"{{ "x": {}, "y": {}, "width": 16, "height": 16, "data": {:?} }}"#,
+                origin.x, origin.y, data
+            )
+        };
+        let json = format!(
+            r#"{{
+                "width": 16, "height": 16, "tilewidth": 32, "tileheight": 32, "infinite": true,
+                "layers": [{{ "name": "floor", "visible": true, "chunks": [{}, {}] }}],
+                "tilesets": [{{ "firstgid": 1, "image": "test.png", "tilecount": 35, "tilewidth": 32, "tileheight": 32 }}]
+            }}"#,
+            chunk(IVec2::splat(-CHUNK_SIZE)),
+            chunk(IVec2::ZERO)
+        );
+        TiledMap::parse(&json).unwrap()
+    }
+
+    #[test]
+    fn chunks_are_found_a_row_down() {
+        // tile positions sit a row above the layer's own coordinates
+        assert_eq!(chunk_of(IVec2::new(0, -1)), (IVec2::ZERO, 0));
+        assert_eq!(chunk_of(IVec2::new(15, 14)), (IVec2::ZERO, 255));
+        assert_eq!(chunk_of(IVec2::new(0, 15)), (IVec2::Y, 0));
+        assert_eq!(chunk_of(IVec2::new(-1, -2)), (IVec2::NEG_ONE, 255));
+        assert_eq!(chunk_of(IVec2::new(-16, -17)), (IVec2::NEG_ONE, 0));
+        assert_eq!(chunk_of(IVec2::new(-17, -1)), (IVec2::new(-2, 0), 15));
+
+        for chunk in [IVec2::NEG_ONE, IVec2::ZERO, IVec2::new(3, -2)] {
+            for (i, pos) in chunk_positions(chunk).enumerate() {
+                assert_eq!(chunk_of(pos), (chunk, i));
+            }
+        }
+    }
+
+    #[test]
+    fn infinite_layers_read_with_the_shift() {
+        let map = infinite_map();
+        let floor = map.get_layer("floor").unwrap();
+
+        for raw in [
+            IVec2::NEG_ONE * 16,
+            IVec2::NEG_ONE,
+            IVec2::ZERO,
+            IVec2::splat(15),
+        ] {
+            assert_eq!(
+                floor.tile_id(raw - IVec2::Y),
+                Some(infinite_gid(raw)),
+                "{raw}"
+            );
+        }
+        // between the chunks there's nothing
+        assert_eq!(floor.tile_id(IVec2::new(-1, 5)), Some(0));
+    }
+
+    #[test]
+    fn chunks_survive_a_round_trip() {
+        let mut map = infinite_map();
+        let chunks = [IVec2::NEG_ONE, IVec2::ZERO];
+        let read: Vec<_> = chunks.map(|c| (c, map.read_chunk(c))).into();
+        assert!(read.iter().all(|(_, layers)| layers.len() == 1));
+
+        map.clear_chunks();
+        let floor = map.get_layer("floor").unwrap();
+        assert!(
+            chunks
+                .iter()
+                .flat_map(|&c| chunk_positions(c))
+                .all(|pos| floor.tile_id(pos) == Some(0))
+        );
+
+        for (chunk, layers) in read {
+            for (layer, data) in layers {
+                map.write_chunk(layer, chunk, data);
+            }
+        }
+        let floor = map.get_layer("floor").unwrap();
+        for pos in chunks.iter().flat_map(|&c| chunk_positions(c)) {
+            assert_eq!(
+                floor.tile_id(pos),
+                Some(infinite_gid(pos + IVec2::Y)),
+                "{pos}"
+            );
+        }
+    }
+
+    // a valid 2x2 map, changed by `edit` before it's loaded
+    fn map_issues(edit: impl FnOnce(&mut Value)) -> Vec<MapIssue> {
+        let mut map = json!({
+            "width": 2, "height": 2, "tilewidth": 32, "tileheight": 32,
+            "layers": [
+                { "name": "floor", "visible": true, "width": 2, "height": 2, "data": [1, 2, 2, 1] },
+                { "name": "spawns", "visible": true, "objects": [
+                    { "id": 1, "name": "player", "x": 32, "y": 32, "width": 32, "height": 32 }
+                ] }
+            ],
+            "tilesets": [{
+                "firstgid": 1, "name": "tiles", "image": "test.png", "tilecount": 2,
+                "tilewidth": 32, "tileheight": 32,
+                "tiles": [{ "id": 0, "animation": [
+                    { "tileid": 0, "duration": 100 }, { "tileid": 1, "duration": 100 }
+                ] }]
+            }]
+        });
+        edit(&mut map);
+        match TiledMap::parse(&map.to_string()) {
+            Ok(_) => Vec::new(),
+            Err(MapError::Invalid(issues)) => issues,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn valid_maps_have_no_issues() {
+        assert!(map_issues(|_| {}).is_empty());
+    }
+
+    #[test]
+    fn reports_unsupported_orientations() {
+        let issues = map_issues(|map| map["orientation"] = json!("isometric"));
+        assert!(matches!(&issues[..], [MapIssue::UnsupportedOrientation(o)] if o == "isometric"));
+    }
+
+    #[test]
+    fn reports_layers_of_the_wrong_size() {
+        let issues = map_issues(|map| map["layers"][0]["width"] = json!(1));
+        assert!(matches!(
+            &issues[..],
+            [
+                MapIssue::LayerSize {
+                    width: 1,
+                    height: 2,
+                    ..
+                },
+                MapIssue::LayerDataLength { len: 4, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_unknown_tiles() {
+        let issues = map_issues(|map| map["layers"][0]["data"][3] = json!(3));
+        assert!(matches!(
+            &issues[..],
+            [MapIssue::UnknownTile { gid: 3, .. }]
+        ));
+
+        // flip flags aren't part of the tile
+        let flipped = FLIPPED_HORIZONTALLY | 2;
+        assert!(map_issues(|map| map["layers"][0]["data"][0] = json!(flipped)).is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_animation_frames() {
+        let issues =
+            map_issues(|map| map["tilesets"][0]["tiles"][0]["animation"][1]["tileid"] = json!(5));
+        assert!(matches!(
+            &issues[..],
+            [MapIssue::UnknownAnimationFrame {
+                tile: 0,
+                frame: 5,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn reports_tilesets_without_an_image_or_size() {
+        let issues = map_issues(|map| {
+            let tileset = map["tilesets"][0].as_object_mut().unwrap();
+            tileset.remove("image");
+            tileset.remove("tilewidth");
+        });
+        assert!(matches!(
+            &issues[..],
+            [
+                MapIssue::MissingTilesetImage { .. },
+                MapIssue::MissingTileSize { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_objects_off_the_map() {
+        let issues = map_issues(|map| map["layers"][1]["objects"][0]["x"] = json!(-10));
+        assert!(matches!(
+            &issues[..],
+            [MapIssue::ObjectOutOfBounds { object, .. }] if object == "player"
+        ));
+
+        // infinite maps have no edges
+        let issues = map_issues(|map| {
+            map["infinite"] = json!(true);
+            map["layers"].as_array_mut().unwrap().remove(0);
+            map["layers"][0]["objects"][0]["x"] = json!(-10);
+        });
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn reports_every_issue_at_once() {
+        let issues = map_issues(|map| {
+            map["orientation"] = json!("hexagonal");
+            map["layers"][0]["data"][3] = json!(9);
+            map["layers"][1]["objects"][0]["y"] = json!(1000);
+        });
+        assert_eq!(issues.len(), 3, "{issues:?}");
+    }
+
+    #[test]
+    fn tiles_are_laid_out_around_margins_and_spacing() {
+        let mut tileset: TiledTileset = serde_json::from_value(json!({
+            "image": "test.png", "tilewidth": 16, "tileheight": 16,
+            "imagewidth": 70, "imageheight": 36, "margin": 2, "spacing": 2
+        }))
+        .unwrap();
+        // (70 + 2 - 4) / 18 columns and (36 + 2 - 4) / 18 rows
+        assert_eq!(tileset.grid(70, 36), Some(UVec2::new(3, 1)));
+        assert_eq!(tileset.tile_count(), Some(3));
+        assert_eq!(tileset.tile_origin(0, 3), Some(UVec2::new(2, 2)));
+        assert_eq!(tileset.tile_origin(4, 3), Some(UVec2::new(20, 20)));
+
+        // a written count wins over the image
+        tileset.tilecount = Some(2);
+        assert_eq!(tileset.tile_count(), Some(2));
+        tileset.tilecount = None;
+        tileset.imagewidth = None;
+        assert_eq!(tileset.tile_count(), None);
+    }
+
+    #[test]
+    fn decodes_gid_flips() {
+        assert_eq!(decode_gid(42), (42, TileFlip::default()));
+
+        let flips = [
+            (FLIPPED_HORIZONTALLY, (true, false, false)),
+            (FLIPPED_VERTICALLY, (false, true, false)),
+            (FLIPPED_DIAGONALLY, (false, false, true)),
+            (
+                FLIPPED_HORIZONTALLY | FLIPPED_DIAGONALLY,
+                (true, false, true),
+            ),
+            (
+                FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY,
+                (true, true, false),
+            ),
+            (FLIP_FLAGS, (true, true, true)),
+        ];
+        for (flags, (horizontal, vertical, diagonal)) in flips {
+            let flip = TileFlip {
+                horizontal,
+                vertical,
+                diagonal,
+            };
+            assert_eq!(decode_gid(42 | flags), (42, flip), "{flags:#x}");
+        }
+    }
+
+    #[test]
+    fn strips_the_hexagonal_flag() {
+        assert_eq!(decode_gid(7 | ROTATED_HEXAGONAL), (7, TileFlip::default()));
+    }
+
+    // a 4x4 map with one marked tile at column 1, row 2 of the layer, covered
+    // by both a plain rectangle and a tile object
+    const MARKED_MAP: &str = r#"{
+        "width": 4, "height": 4, "tilewidth": 32, "tileheight": 32,
+        "layers": [
+            { "name": "floor", "visible": true, "width": 4, "height": 4,
+              "data": [1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1] },
+            { "name": "marks", "visible": true, "objects": [
+                { "id": 1, "name": "rect", "x": 32, "y": 64, "width": 32, "height": 32 },
+                { "id": 2, "name": "tile", "gid": 2, "x": 32, "y": 96, "width": 32, "height": 32 }
+            ] }
+        ],
+        "tilesets": [{ "firstgid": 1, "image": "test.png", "tilecount": 2, "tilewidth": 32, "tileheight": 32 }]
+    }"#;
+
+    #[test]
+    fn objects_cover_the_tiles_drawn_under_them() {
+        let map = TiledMap::parse(MARKED_MAP).unwrap();
+        let floor = map.get_layer("floor").unwrap();
+
+        for name in ["rect", "tile"] {
+            let object = map.get_object("marks", name).unwrap();
+            let (min, max) = map.object_tiles(object);
+            assert_eq!(max - min, IVec2::ONE, "{name}");
+            assert_eq!(floor.tile_id(min), Some(2), "{name}");
+        }
+    }
+}
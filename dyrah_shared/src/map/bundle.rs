@@ -0,0 +1,323 @@
+// everything a client needs to load a map: the map file, its external
+// tilesets and their images, so the server can hand out its own copy
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use super::{TiledMap, tmx};
+
+// a bundle's files are written out under one directory, so their paths can
+// only go down from it; `./` is dropped and anything else is refused.
+// backslashes are separators too, whichever system the path came from
+fn bundle_path(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let mut parts = Vec::new();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(part) if !part.to_str()?.contains(':') => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+// map names are file stems, so they can be used in paths as they are; the
+// server's are checked before they are
+pub fn is_map_name(name: &str) -> bool {
+    bundle_path(name).is_some_and(|path| path == name && !path.contains('/'))
+}
+
+// the map file as it's sent; infinite maps are streamed chunk by chunk as
+// players get close, so their chunks are left out and the rest is sent as
+// JSON whatever it was saved as
+fn map_file(path: &Path, tiled: &TiledMap) -> io::Result<BundleFile> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad map path"))?;
+    let bytes = fs::read(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    if !tiled.infinite {
+        return Ok(BundleFile {
+            path: name.to_string(),
+            bytes,
+        });
+    }
+
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let content = String::from_utf8(bytes).map_err(|e| invalid(e.to_string()))?;
+    let mut map = match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") => tmx::map_value(&content).map_err(|e| invalid(e.to_string()))?,
+        _ => serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?,
+    };
+    strip_chunks(&mut map);
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    Ok(BundleFile {
+        path: format!("{}.json", stem),
+        bytes: serde_json::to_vec(&map).map_err(|e| invalid(e.to_string()))?,
+    })
+}
+
+// empties the chunks of every layer, including those inside groups
+fn strip_chunks(value: &mut Value) {
+    let Some(layers) = value.get_mut("layers").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for layer in layers {
+        if let Some(chunks) = layer.get_mut("chunks") {
+            *chunks = Value::Array(Vec::new());
+        }
+        strip_chunks(layer);
+    }
+}
+
+pub struct BundleFile {
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct MapBundle {
+    pub files: Vec<BundleFile>,
+    // tilesets and images the map names that aren't there, left out of the
+    // bundle and its hash so the map still loads without them
+    pub missing: Vec<String>,
+}
+
+impl MapBundle {
+    // paths are relative to the map's directory, with the map file first;
+    // tilesets and images outside of it (like `../tiles.png`) can't be sent,
+    // so the map is refused here instead of by every client
+    pub fn collect(path: &Path, tiled: &TiledMap) -> io::Result<Self> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let map_file = map_file(path, tiled)?;
+
+        let mut paths = Vec::new();
+        for tileset in &tiled.tilesets {
+            paths.extend(tileset.source.iter().cloned());
+            paths.extend(tileset.image.iter().cloned());
+        }
+
+        let mut files = vec![map_file];
+        let mut missing = Vec::new();
+        for path in paths {
+            let path = bundle_path(&path).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is outside the map's directory", path),
+                )
+            })?;
+            if files.iter().any(|f| f.path == path) || missing.contains(&path) {
+                continue;
+            }
+
+            let full_path = dir.join(&path);
+            match fs::read(&full_path) {
+                Ok(bytes) => files.push(BundleFile { path, bytes }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push(path),
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: {}", full_path.display(), e),
+                    ));
+                }
+            }
+        }
+
+        Ok(Self { files, missing })
+    }
+
+    // FNV-1a, so every build of the client and server agrees on it
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for &b in bytes {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+
+        for file in &self.files {
+            write(file.path.as_bytes());
+            write(&(file.bytes.len() as u64).to_le_bytes());
+            write(&file.bytes);
+        }
+
+        hash
+    }
+
+    // writes the bundle out under `dir` and returns the path of the map file;
+    // paths come from the network, so anything escaping `dir` is refused
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let map_file = self
+            .files
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty map bundle"))?;
+
+        for file in &self.files {
+            let relative = bundle_path(&file.path).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsafe path {} in map bundle", file.path),
+                )
+            })?;
+
+            let path = dir.join(relative);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, &file.bytes)?;
+        }
+
+        Ok(dir.join(&map_file.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory per test, so they can run side by side
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dyrah-bundle-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keeps_paths_under_the_directory() {
+        assert_eq!(bundle_path("tiles.png").as_deref(), Some("tiles.png"));
+        assert_eq!(bundle_path("./tiles.png").as_deref(), Some("tiles.png"));
+        assert_eq!(
+            bundle_path("art/./tiles.png").as_deref(),
+            Some("art/tiles.png")
+        );
+        assert_eq!(
+            bundle_path("art\\tiles.png").as_deref(),
+            Some("art/tiles.png")
+        );
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_directory() {
+        for path in [
+            "",
+            ".",
+            "..",
+            "../tiles.png",
+            "art/../../tiles.png",
+            "/etc/passwd",
+            "..\\tiles.png",
+            "art\\..\\..\\tiles.png",
+            "\\\\server\\share\\tiles.png",
+            "C:\\tiles.png",
+            "C:tiles.png",
+        ] {
+            assert_eq!(bundle_path(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn map_names_are_single_file_names() {
+        assert!(is_map_name("cave"));
+        for name in ["", "./cave", "../cave", "maps/cave", "maps\\cave", "/cave"] {
+            assert!(!is_map_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn save_refuses_files_leaving_the_directory() {
+        let dir = temp_dir("save");
+        let bundle = MapBundle {
+            files: vec![
+                BundleFile {
+                    path: "map.json".to_string(),
+                    bytes: b"{}".to_vec(),
+                },
+                BundleFile {
+                    path: "../escaped.txt".to_string(),
+                    bytes: b"gotcha".to_vec(),
+                },
+            ],
+            missing: Vec::new(),
+        };
+
+        assert!(bundle.save(&dir.join("out")).is_err());
+        assert!(!dir.join("escaped.txt").exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn collect_leaves_out_missing_images() {
+        let dir = temp_dir("collect");
+        let map = r#"{
+            "width": 1, "height": 1, "tilewidth": 32, "tileheight": 32,
+            "layers": [{ "name": "floor", "visible": true, "width": 1, "height": 1, "data": [1] }],
+            "tilesets": [
+                { "firstgid": 1, "image": "present.png", "tilecount": 1, "tilewidth": 32, "tileheight": 32 },
+                { "firstgid": 2, "image": "./missing.png", "tilecount": 1, "tilewidth": 32, "tileheight": 32 }
+            ]
+        }"#;
+        let path = dir.join("map.json");
+        fs::write(&path, map).unwrap();
+        fs::write(dir.join("present.png"), b"png").unwrap();
+
+        let bundle = MapBundle::collect(&path, &TiledMap::load(&path).unwrap()).unwrap();
+        let paths: Vec<&str> = bundle.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["map.json", "present.png"]);
+        assert_eq!(bundle.missing, ["missing.png"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn infinite_maps_are_sent_without_their_chunks() {
+        let dir = temp_dir("infinite");
+        let map = |gid: u32| {
+            format!(
+                r#"{{
+                    "width": 16, "height": 16, "tilewidth": 32, "tileheight": 32, "infinite": true,
+                    "layers": [{{ "name": "floor", "visible": true, "chunks": [
+                        {{ "x": -16, "y": 0, "width": 16, "height": 16, "data": {:?} }}
+                    ] }}],
+                    "tilesets": [{{ "firstgid": 1, "image": "tiles.png", "tilecount": 2, "tilewidth": 32, "tileheight": 32 }}]
+                }}"#,
+                vec![gid; 256]
+            )
+        };
+        let bundle = |gid: u32| {
+            let path = dir.join("world.json");
+            fs::write(&path, map(gid)).unwrap();
+            MapBundle::collect(&path, &TiledMap::load(&path).unwrap()).unwrap()
+        };
+
+        let (first, second) = (bundle(1), bundle(2));
+        assert_eq!(first.hash(), second.hash());
+
+        let sent = TiledMap::parse(std::str::from_utf8(&first.files[0].bytes).unwrap()).unwrap();
+        assert!(sent.infinite);
+        assert!(sent.layers[0].chunks.as_ref().unwrap().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn collect_names_a_missing_map_file() {
+        let dir = temp_dir("missing");
+        let tiled = TiledMap::parse(
+            r#"{ "width": 0, "height": 0, "tilewidth": 32, "tileheight": 32, "layers": [], "tilesets": [] }"#,
+        )
+        .unwrap();
+
+        let error = MapBundle::collect(&dir.join("gone.json"), &tiled)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("gone.json"), "{error}");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
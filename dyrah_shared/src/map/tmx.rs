@@ -0,0 +1,397 @@
+// TMX/TSX maps are rewritten into the same shape as Tiled's JSON export so
+// both formats share one set of types and one validation pass
+
+use roxmltree::{Document, Node};
+use serde_json::{Map as JsonMap, Value, from_value, json};
+
+use super::{MapError, TiledMap, TiledTileset};
+
+pub(super) fn parse_map(content: &str) -> Result<TiledMap, MapError> {
+    Ok(from_value(map_value(content)?)?)
+}
+
+// the map as Tiled's JSON export would have it
+pub(super) fn map_value(content: &str) -> Result<Value, MapError> {
+    let doc = Document::parse(content)?;
+    let root = doc.root_element();
+    if !root.has_tag_name("map") {
+        return Err(MapError::Format(format!(
+            "expected <map>, found <{}>",
+            root.tag_name().name()
+        )));
+    }
+
+    let mut map = attributes(root);
+    map.insert(
+        "infinite".to_string(),
+        json!(root.attribute("infinite") == Some("1")),
+    );
+    map.insert("layers".to_string(), Value::Array(layers(root)?));
+    map.insert(
+        "tilesets".to_string(),
+        Value::Array(children(root, "tileset").map(tileset).collect()),
+    );
+    map.insert("properties".to_string(), properties(root));
+
+    Ok(Value::Object(map))
+}
+
+pub(super) fn parse_tileset(content: &str) -> Result<TiledTileset, MapError> {
+    let doc = Document::parse(content)?;
+    let root = doc.root_element();
+    if !root.has_tag_name("tileset") {
+        return Err(MapError::Format(format!(
+            "expected <tileset>, found <{}>",
+            root.tag_name().name()
+        )));
+    }
+
+    Ok(from_value(tileset(root))?)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    tag: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.has_tag_name(tag))
+}
+
+// attributes that hold numbers in the JSON export, everything else stays a
+// string even when it looks numeric (a layer named "1" is still a name)
+const NUMERIC: &[&str] = &[
+    "id",
+    "x",
+    "y",
+    "width",
+    "height",
+    "tilewidth",
+    "tileheight",
+    "firstgid",
+    "tilecount",
+    "margin",
+    "spacing",
+    "tileid",
+    "duration",
+    "gid",
+];
+
+fn attr_value(name: &str, value: &str) -> Value {
+    if !NUMERIC.contains(&name) {
+        return json!(value);
+    }
+
+    if let Ok(int) = value.parse::<i64>() {
+        json!(int)
+    } else if let Ok(float) = value.parse::<f64>() {
+        json!(float)
+    } else {
+        json!(value)
+    }
+}
+
+fn attributes(node: Node) -> JsonMap<String, Value> {
+    node.attributes()
+        .map(|a| (a.name().to_string(), attr_value(a.name(), a.value())))
+        .collect()
+}
+
+fn layers(node: Node) -> Result<Vec<Value>, MapError> {
+    let mut list = Vec::new();
+
+    for child in node.children().filter(Node::is_element) {
+        let mut layer = attributes(child);
+        layer.entry("name").or_insert(json!(""));
+        layer.insert(
+            "visible".to_string(),
+            json!(child.attribute("visible") != Some("0")),
+        );
+        layer.insert("properties".to_string(), properties(child));
+
+        match child.tag_name().name() {
+            "layer" => {
+                if let Some(data) = child.children().find(|n| n.has_tag_name("data")) {
+                    layer.extend(tile_data(data)?);
+                }
+            }
+            "objectgroup" => {
+                let objects = children(child, "object").map(object).collect();
+                layer.insert("objects".to_string(), Value::Array(objects));
+            }
+            "group" => {
+                layer.insert("layers".to_string(), Value::Array(layers(child)?));
+            }
+            "imagelayer" => {}
+            _ => continue,
+        }
+
+        list.push(Value::Object(layer));
+    }
+
+    Ok(list)
+}
+
+fn tile_data(data: Node) -> Result<JsonMap<String, Value>, MapError> {
+    let mut fields = JsonMap::new();
+    if let Some(encoding) = data.attribute("encoding") {
+        fields.insert("encoding".to_string(), json!(encoding));
+    }
+    if let Some(compression) = data.attribute("compression") {
+        fields.insert("compression".to_string(), json!(compression));
+    }
+
+    // infinite maps keep their tiles in chunks, each encoded like the layer
+    if data.children().any(|n| n.has_tag_name("chunk")) {
+        let chunks = children(data, "chunk")
+            .map(|chunk| {
+                let mut fields = attributes(chunk);
+                fields.insert("data".to_string(), tiles(chunk)?);
+                Ok(Value::Object(fields))
+            })
+            .collect::<Result<_, MapError>>()?;
+        fields.insert("chunks".to_string(), Value::Array(chunks));
+    } else {
+        fields.insert("data".to_string(), tiles(data)?);
+    }
+
+    Ok(fields)
+}
+
+// encoded data is decoded alongside the JSON export's base64 strings, while
+// plain XML data is a <tile> element per tile
+fn tiles(node: Node) -> Result<Value, MapError> {
+    if let Some(text) = node.text().filter(|t| !t.trim().is_empty()) {
+        return Ok(json!(text));
+    }
+
+    let gids = children(node, "tile")
+        .map(|t| t.attribute("gid").map_or(Ok(0), str::parse::<u32>))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| MapError::Format(format!("bad tile gid: {}", e)))?;
+    Ok(json!(gids))
+}
+
+fn object(node: Node) -> Value {
+    let mut object = attributes(node);
+    object.entry("name").or_insert(json!(""));
+    if !object.contains_key("class") {
+        object.entry("type").or_insert(json!(""));
+    }
+    for field in ["width", "height"] {
+        object.entry(field).or_insert(json!(0));
+    }
+    object.insert("properties".to_string(), properties(node));
+
+    Value::Object(object)
+}
+
+fn tileset(node: Node) -> Value {
+    let mut tileset = attributes(node);
+
+    if let Some(offset) = node.children().find(|n| n.has_tag_name("tileoffset")) {
+        tileset.insert("tileoffset".to_string(), Value::Object(attributes(offset)));
+    }
+    if let Some(image) = node.children().find(|n| n.has_tag_name("image")) {
+        let image = attributes(image);
+        if let Some(source) = image.get("source") {
+            tileset.insert("image".to_string(), source.clone());
+        }
+        for (attribute, field) in [("width", "imagewidth"), ("height", "imageheight")] {
+            if let Some(size) = image.get(attribute) {
+                tileset.insert(field.to_string(), size.clone());
+            }
+        }
+    }
+
+    let tiles = children(node, "tile")
+        .map(|tile| {
+            let mut fields = attributes(tile);
+            fields.insert("properties".to_string(), properties(tile));
+
+            let frames = tile
+                .children()
+                .find(|n| n.has_tag_name("animation"))
+                .into_iter()
+                .flat_map(|animation| children(animation, "frame"))
+                .map(|frame| Value::Object(attributes(frame)))
+                .collect();
+            fields.insert("animation".to_string(), Value::Array(frames));
+            Value::Object(fields)
+        })
+        .collect();
+    tileset.insert("tiles".to_string(), Value::Array(tiles));
+    tileset.insert("properties".to_string(), properties(node));
+
+    Value::Object(tileset)
+}
+
+// the JSON property list: [{ "name", "type", "value" }]
+fn properties(node: Node) -> Value {
+    let list = node
+        .children()
+        .find(|n| n.has_tag_name("properties"))
+        .into_iter()
+        .flat_map(|props| children(props, "property"))
+        .map(|prop| {
+            let kind = prop.attribute("type").unwrap_or("string");
+            json!({
+                "name": prop.attribute("name").unwrap_or_default(),
+                "type": kind,
+                "value": property_value(prop, kind),
+            })
+        })
+        .collect();
+
+    Value::Array(list)
+}
+
+fn property_value(prop: Node, kind: &str) -> Value {
+    if kind == "class" {
+        // class members are nested properties, stored by name like the JSON export
+        let members = prop
+            .children()
+            .find(|n| n.has_tag_name("properties"))
+            .into_iter()
+            .flat_map(|props| children(props, "property"))
+            .map(|member| {
+                let kind = member.attribute("type").unwrap_or("string");
+                let name = member.attribute("name").unwrap_or_default().to_string();
+                (name, property_value(member, kind))
+            })
+            .collect();
+        return Value::Object(members);
+    }
+
+    // multi-line strings are stored as text instead of a value attribute
+    let text = prop
+        .attribute("value")
+        .or_else(|| prop.text())
+        .unwrap_or_default();
+
+    // values that fail to parse are left as strings for the property to reject
+    match kind {
+        "bool" => text
+            .parse::<bool>()
+            .map_or_else(|_| json!(text), |b| json!(b)),
+        "int" | "object" => text
+            .parse::<i64>()
+            .map_or_else(|_| json!(text), |i| json!(i)),
+        "float" => text
+            .parse::<f64>()
+            .map_or_else(|_| json!(text), |f| json!(f)),
+        _ => json!(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+    use flate2::{
+        Compression,
+        write::{GzEncoder, ZlibEncoder},
+    };
+
+    use super::*;
+    use crate::map::MapIssue;
+
+    const GIDS: [u32; 4] = [1, 0, 2, 1];
+
+    // a 2x2 map with a single layer holding the given data
+    fn tmx(encoding: &str, compression: Option<&str>, data: &str) -> String {
+        let compression = compression.map_or(String::new(), |c| format!(r#" compression="{c}""#));
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="2" height="2" tilewidth="32" tileheight="32" infinite="0">
+ <tileset firstgid="1" name="test" tilewidth="32" tileheight="32" tilecount="2" columns="2">
+  <image source="test.png" width="64" height="32"/>
+ </tileset>
+ <layer id="1" name="floor" width="2" height="2">
+  <data encoding="{encoding}"{compression}>{data}</data>
+ </layer>
+</map>"#
+        )
+    }
+
+    fn load(content: &str) -> Result<TiledMap, MapError> {
+        parse_map(content)?.finish(None)
+    }
+
+    fn floor(content: &str) -> Vec<u32> {
+        let map = load(content).unwrap();
+        map.get_layer("floor").unwrap().data.clone().unwrap()
+    }
+
+    fn encode(compression: Option<&str>) -> String {
+        let raw: Vec<u8> = GIDS.iter().flat_map(|gid| gid.to_le_bytes()).collect();
+        let bytes = match compression {
+            None => raw,
+            Some("zlib") => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&raw).unwrap();
+                encoder.finish().unwrap()
+            }
+            Some("gzip") => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&raw).unwrap();
+                encoder.finish().unwrap()
+            }
+            Some("zstd") => zstd::encode_all(&raw[..], 0).unwrap(),
+            Some(other) => panic!("no encoder for {other}"),
+        };
+        BASE64.encode(bytes)
+    }
+
+    fn issues(content: &str) -> Vec<MapIssue> {
+        match load(content) {
+            Err(MapError::Invalid(issues)) => issues,
+            Err(e) => panic!("expected an invalid map, got {e}"),
+            Ok(_) => panic!("expected an invalid map"),
+        }
+    }
+
+    #[test]
+    fn decodes_csv() {
+        assert_eq!(floor(&tmx("csv", None, "\n1,0,\n2,1\n")), GIDS);
+    }
+
+    #[test]
+    fn decodes_base64() {
+        for compression in [None, Some("zlib"), Some("gzip"), Some("zstd")] {
+            let data = format!("\n   {}\n", encode(compression));
+            assert_eq!(
+                floor(&tmx("base64", compression, &data)),
+                GIDS,
+                "{compression:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_base64() {
+        let issues = issues(&tmx("base64", None, "not*base64"));
+        assert!(matches!(issues[..], [MapIssue::BadLayerData { .. }]));
+    }
+
+    #[test]
+    fn rejects_partial_tiles() {
+        let data = BASE64.encode([1, 0, 0, 0, 2]);
+        let issues = issues(&tmx("base64", None, &data));
+        assert!(matches!(issues[..], [MapIssue::BadLayerData { .. }]));
+    }
+
+    #[test]
+    fn rejects_unknown_compression() {
+        let issues = issues(&tmx("base64", Some("lzma"), &encode(None)));
+        assert!(matches!(issues[..], [MapIssue::BadLayerData { .. }]));
+    }
+
+    #[test]
+    fn rejects_wrong_tile_count() {
+        let issues = issues(&tmx("csv", None, "1,0,2"));
+        assert!(matches!(
+            issues[..],
+            [MapIssue::LayerDataLength { len: 3, .. }]
+        ));
+    }
+}
@@ -1,18 +1,177 @@
 use glam::{IVec2, Vec2};
 use serde::{Deserialize, Serialize};
 
-use crate::NetId;
+use crate::{NetId, conditions::ConditionKind};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessage {
-    PlayerSpawned { id: NetId, position: Vec2 },
-    PlayerDespawned { id: NetId },
-    PlayerMoved { id: NetId, position: Vec2 },
+    MapChanged {
+        map: String,
+        hash: u64,
+        position: Vec2,
+        floor: i32,
+    },
+    PlayerSpawned {
+        id: NetId,
+        name: String,
+        position: Vec2,
+        floor: i32,
+        // percent of max health and mana
+        health: u8,
+        mana: u8,
+        conditions: Vec<ConditionKind>,
+    },
+    PlayerDespawned {
+        id: NetId,
+    },
+    PlayerMoved {
+        id: NetId,
+        position: Vec2,
+        floor: i32,
+    },
+    // `change` is in points, negative for damage, and `source` is whoever
+    // caused it
+    HealthChanged {
+        id: NetId,
+        health: u8,
+        change: i32,
+        source: Option<NetId>,
+    },
+    ManaChanged {
+        id: NetId,
+        mana: u8,
+    },
+    ConditionsChanged {
+        id: NetId,
+        conditions: Vec<ConditionKind>,
+    },
+    // everyone in the player's party, themselves included, or nobody
+    PartyChanged {
+        members: Vec<NetId>,
+    },
+    // an animation played once on each of `tiles`, over `duration` seconds
+    // and starting after `delay`, so explosions can wait for what's thrown
+    TileEffect {
+        effect: EffectKind,
+        tiles: Vec<IVec2>,
+        floor: i32,
+        duration: f32,
+        delay: f32,
+    },
+    // something flying from one tile to another, arriving after `duration`
+    Projectile {
+        effect: EffectKind,
+        origin: IVec2,
+        target: IVec2,
+        floor: i32,
+        duration: f32,
+    },
+    // the player's rune charges, by rune
+    Runes {
+        runes: Vec<(String, u32)>,
+    },
+    // anything the player should read, like what they looked at
+    TextMessage {
+        text: String,
+    },
+    // the files of a map the client asked for, each followed by its parts
+    MapDownload {
+        map: String,
+        hash: u64,
+        files: Vec<(String, u32)>,
+    },
+    MapFilePart {
+        map: String,
+        path: String,
+        offset: u32,
+        bytes: Vec<u8>,
+    },
+    // tiles of one chunk of an infinite map, per layer index
+    MapChunk {
+        map: String,
+        chunk: IVec2,
+        layers: Vec<(u32, Vec<u32>)>,
+    },
+    MapChunkUnloaded {
+        map: String,
+        chunk: IVec2,
+    },
+    // seconds into the current day
+    WorldTime {
+        time: f32,
+    },
+    ShutdownWarning {
+        seconds: u32,
+    },
+    Disconnected {
+        reason: String,
+    },
+}
+
+// the animations clients know how to play, each a row of the effects sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectKind {
+    Poof,
+    Blood,
+    Fire,
+    Energy,
+    Ice,
+    Poison,
+    Holy,
+    Death,
+    Heal,
+}
+
+impl EffectKind {
+    pub fn row(&self) -> usize {
+        *self as usize
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
+    // the first thing a client sends, naming the character to play
+    Login { name: String },
     PlayerUpdate { input: ClientInput },
+    RequestMap { map: String },
+    Interact { target: NetId, action: EntityAction },
+    // stops attacking and following
+    CancelActions,
+    // spoken aloud, which casts the spell if it's an incantation
+    Say { text: String },
+    // casts a rune at the player's attack target
+    UseRune { rune: String },
+}
+
+// what can be done to another entity from its context menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntityAction {
+    Look,
+    Attack,
+    Follow,
+    Trade,
+    InviteToParty,
+}
+
+impl EntityAction {
+    pub const ALL: [Self; 5] = [
+        Self::Look,
+        Self::Attack,
+        Self::Follow,
+        Self::Trade,
+        Self::InviteToParty,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Look => "Look",
+            Self::Attack => "Attack",
+            Self::Follow => "Follow",
+            Self::Trade => "Trade with",
+            Self::InviteToParty => "Invite to party",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,285 @@
+// spells are data, loaded from a json file so they can be tuned without
+// touching the code
+
+use std::{fs, io, path::Path};
+
+use glam::{IVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{conditions::ConditionKind, los, messages::EffectKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Fire,
+    Energy,
+    Earth,
+    Ice,
+    Holy,
+    Death,
+    Healing,
+}
+
+impl DamageType {
+    // what clients see where it lands
+    pub fn effect(&self) -> EffectKind {
+        match self {
+            Self::Physical => EffectKind::Blood,
+            Self::Fire => EffectKind::Fire,
+            Self::Energy => EffectKind::Energy,
+            Self::Earth => EffectKind::Poison,
+            Self::Ice => EffectKind::Ice,
+            Self::Holy => EffectKind::Holy,
+            Self::Death => EffectKind::Death,
+            Self::Healing => EffectKind::Heal,
+        }
+    }
+}
+
+// casting a spell puts every spell in its group on cooldown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CooldownGroup {
+    Attack,
+    Healing,
+    Support,
+}
+
+// the tiles a spell lands on; everything aimed needs a target
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Area {
+    #[default]
+    Caster,
+    Target,
+    // around the caster
+    Circle {
+        radius: i32,
+    },
+    // around the target
+    TargetCircle {
+        radius: i32,
+    },
+    // a straight line from the caster toward the target
+    Beam {
+        length: i32,
+    },
+    // a cone from the caster toward the target
+    Wave {
+        length: i32,
+    },
+}
+
+impl Area {
+    pub fn needs_target(&self) -> bool {
+        !matches!(self, Self::Caster | Self::Circle { .. })
+    }
+
+    // thrown at the target rather than cast from the caster
+    pub fn is_thrown(&self) -> bool {
+        matches!(self, Self::Target | Self::TargetCircle { .. })
+    }
+
+    // None when it needs a target and has none
+    pub fn tiles(&self, caster: IVec2, target: Option<IVec2>) -> Option<Vec<IVec2>> {
+        let target = match (self.needs_target(), target) {
+            (true, None) => return None,
+            (_, target) => target.unwrap_or(caster),
+        };
+        let direction = (target - caster).as_vec2().normalize_or_zero();
+
+        Some(match *self {
+            Self::Caster => vec![caster],
+            Self::Target => vec![target],
+            Self::Circle { radius } => circle(caster, radius),
+            Self::TargetCircle { radius } => circle(target, radius),
+            Self::Beam { length } => {
+                let end = caster + (direction * length as f32).round().as_ivec2();
+                los::line(caster, end).skip(1).collect()
+            }
+            Self::Wave { length } => square(caster, length)
+                .filter(|&pos| {
+                    let offset = (pos - caster).as_vec2();
+                    // about 30 degrees either side, so it widens 1, 3, 3, 5..
+                    offset != Vec2::ZERO && offset.normalize().dot(direction) >= 0.85
+                })
+                .collect(),
+        })
+    }
+}
+
+fn square(center: IVec2, radius: i32) -> impl Iterator<Item = IVec2> {
+    (-radius..=radius).flat_map(move |y| (-radius..=radius).map(move |x| center + IVec2::new(x, y)))
+}
+
+// a little over the radius so the edges come out round
+fn circle(center: IVec2, radius: i32) -> Vec<IVec2> {
+    square(center, radius)
+        .filter(|&pos| (pos - center).length_squared() <= radius * radius + radius)
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SpellEffect {
+    Damage {
+        power: u32,
+    },
+    Heal {
+        power: u32,
+    },
+    // makes charges of a rune, which is itself a spell without words
+    Conjure {
+        rune: String,
+        charges: u32,
+    },
+    // harmful ones land on enemies, the rest on friends
+    Condition {
+        condition: ConditionKind,
+        // in seconds
+        duration: f32,
+        #[serde(default)]
+        power: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spell {
+    pub name: String,
+    // what's said to cast it; runes have none and are cast from their charges
+    #[serde(default)]
+    pub words: Option<String>,
+    #[serde(default)]
+    pub mana: u32,
+    pub group: CooldownGroup,
+    // in seconds
+    pub cooldown: f32,
+    #[serde(default = "first_level")]
+    pub level: u32,
+    #[serde(default)]
+    pub area: Area,
+    #[serde(default)]
+    pub damage_type: DamageType,
+    pub effect: SpellEffect,
+    // what clients see, when it isn't the one its effect suggests
+    #[serde(default)]
+    pub animation: Option<EffectKind>,
+}
+
+impl Spell {
+    pub fn animation(&self) -> EffectKind {
+        if let Some(animation) = self.animation {
+            return animation;
+        }
+        match &self.effect {
+            SpellEffect::Heal { .. } => EffectKind::Heal,
+            SpellEffect::Conjure { .. } => EffectKind::Poof,
+            SpellEffect::Condition { condition, .. } if !condition.is_harmful() => EffectKind::Heal,
+            _ => self.damage_type.effect(),
+        }
+    }
+}
+
+fn first_level() -> u32 {
+    1
+}
+
+#[derive(Debug, Default)]
+pub struct Spells {
+    spells: Vec<Spell>,
+}
+
+impl Spells {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self {
+            spells: serde_json::from_str(&content)?,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Spell> {
+        self.spells.iter().find(|s| s.name == name)
+    }
+
+    // incantations are matched however they're capitalised or spaced
+    pub fn by_words(&self, text: &str) -> Option<&Spell> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.spells.iter().find(|s| {
+            s.words
+                .as_ref()
+                .is_some_and(|words| words.eq_ignore_ascii_case(&text))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASTER: IVec2 = IVec2::new(5, 5);
+
+    #[test]
+    fn aimed_areas_need_a_target() {
+        let target = Area::Target.tiles(CASTER, Some(IVec2::new(8, 2)));
+        assert_eq!(target, Some(vec![IVec2::new(8, 2)]));
+        assert_eq!(Area::Target.tiles(CASTER, None), None);
+        assert_eq!(Area::Beam { length: 3 }.tiles(CASTER, None), None);
+        assert_eq!(Area::Caster.tiles(CASTER, None), Some(vec![CASTER]));
+    }
+
+    #[test]
+    fn circles_are_round() {
+        let tiles = Area::Circle { radius: 1 }.tiles(CASTER, None).unwrap();
+        assert_eq!(tiles.len(), 9);
+
+        // the corners of the square are cut off from radius 2 on
+        let tiles = Area::TargetCircle { radius: 2 }
+            .tiles(CASTER, Some(IVec2::ZERO))
+            .unwrap();
+        assert_eq!(tiles.len(), 21);
+        assert!(!tiles.contains(&IVec2::new(2, 2)));
+        assert!(tiles.contains(&IVec2::new(2, 1)));
+    }
+
+    #[test]
+    fn beams_head_for_the_target() {
+        let east = Area::Beam { length: 3 }
+            .tiles(CASTER, Some(CASTER + IVec2::new(10, 0)))
+            .unwrap();
+        assert_eq!(east, [6, 7, 8].map(|x| IVec2::new(x, 5)));
+
+        // the length is the same whatever the direction
+        let diagonal = Area::Beam { length: 3 }
+            .tiles(CASTER, Some(CASTER - IVec2::ONE))
+            .unwrap();
+        assert_eq!(diagonal, [4, 3].map(IVec2::splat));
+    }
+
+    #[test]
+    fn waves_widen_away_from_the_caster() {
+        let tiles = Area::Wave { length: 4 }
+            .tiles(CASTER, Some(CASTER + IVec2::X))
+            .unwrap();
+        assert!(tiles.iter().all(|t| t.x > CASTER.x));
+
+        let widths: Vec<usize> = (1..=4)
+            .map(|d| tiles.iter().filter(|t| t.x == CASTER.x + d).count())
+            .collect();
+        assert_eq!(widths, [1, 3, 3, 5]);
+
+        let north = Area::Wave { length: 1 }
+            .tiles(CASTER, Some(CASTER - IVec2::Y * 3))
+            .unwrap();
+        assert_eq!(north, [CASTER - IVec2::Y]);
+    }
+
+    #[test]
+    fn words_ignore_case_and_spacing() {
+        let spells = Spells::load(Path::new("../assets/data/spells.json")).unwrap();
+        let spell = spells.by_words("  Exevo   GRAN mas vis ").unwrap();
+        assert_eq!(spell.name, "Ultimate Explosion");
+        assert!(spells.by_words("exevo").is_none());
+    }
+}